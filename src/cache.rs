@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use fxhash::FxHashMap;
+
+/// The name of the file in the output directory in which the [RunCache] is stored.
+pub const CACHE_FILE_NAME: &str = "run_cache.json";
+
+/// Remembers the outputs of tests across runs, allowing tests whose ROM didn't change to skip running the emulator.
+///
+/// See [EmuRunnerOptions::emulator_version](crate::options::EmuRunnerOptions::emulator_version).
+#[derive(Debug)]
+pub struct RunCache {
+    emulator_version: String,
+    /// The entries of the previous run, only valid if the ROM of the test still has the same hash.
+    previous: FxHashMap<String, CacheEntry>,
+    /// The hashes of the ROM and seed of the tests of the current run, by `rom_id`.
+    rom_hashes: Mutex<FxHashMap<String, String>>,
+    /// The entries of the current run, which replace the previous entries once saved.
+    current: Mutex<FxHashMap<String, CacheEntry>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+struct CacheFile {
+    emulator_version: String,
+    entries: FxHashMap<String, CacheEntry>,
+}
+
+/// Everything needed to reproduce the output of a single test without running the emulator.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    rom_hash: String,
+    pub sub_tests: Vec<CachedSubTest>,
+    pub text: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+/// The frames of a single sub-test of a cached test.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct CachedSubTest {
+    /// Where the frames were saved in the `new` directory of the run which produced them.
+    pub path: PathBuf,
+    /// Whether the `path` is an animated GIF containing every frame of a sequence test.
+    pub animated: bool,
+    /// The tags of the frames stored in the `path`, in order.
+    pub tags: Vec<Option<String>>,
+}
+
+impl RunCache {
+    /// Load the cache of a previous run from the given `path`.
+    ///
+    /// A missing or unreadable cache, or one created with a different `emulator_version`, results in an empty cache.
+    pub fn load(path: &Path, emulator_version: &str) -> Self {
+        let previous = std::fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<CacheFile>(&data).ok())
+            .filter(|file| file.emulator_version == emulator_version)
+            .map(|file| file.entries)
+            .unwrap_or_default();
+
+        Self {
+            emulator_version: emulator_version.to_string(),
+            previous,
+            rom_hashes: Default::default(),
+            current: Default::default(),
+        }
+    }
+
+    /// Find the entry of the previous run for the given test, if neither its ROM nor its seed changed since.
+    pub fn lookup(&self, rom_id: &str, rom_data: &[u8], seed: u64) -> Option<&CacheEntry> {
+        let rom_hash = blake3::Hasher::new()
+            .update(rom_data)
+            .update(&seed.to_le_bytes())
+            .finalize()
+            .to_hex()
+            .to_string();
+        let entry = self.previous.get(rom_id).filter(|entry| entry.rom_hash == rom_hash);
+
+        self.rom_hashes.lock().unwrap().insert(rom_id.to_string(), rom_hash);
+
+        entry
+    }
+
+    /// Remember the output of the given test for the next run.
+    ///
+    /// Does nothing if the test was never passed to [RunCache::lookup], as its ROM hash is then unknown.
+    pub fn insert(
+        &self,
+        rom_id: &str,
+        sub_tests: Vec<CachedSubTest>,
+        text: Option<String>,
+        metadata: HashMap<String, String>,
+    ) {
+        let Some(rom_hash) = self.rom_hashes.lock().unwrap().get(rom_id).cloned() else {
+            return;
+        };
+
+        self.current.lock().unwrap().insert(
+            rom_id.to_string(),
+            CacheEntry {
+                rom_hash,
+                sub_tests,
+                text,
+                metadata,
+            },
+        );
+    }
+
+    /// Save the entries of the current run to the given `path`, replacing those of the previous run.
+    ///
+    /// Normally only the frames of the current run are kept in the `new` directory, so entries of tests which weren't
+    /// part of the current run are discarded. If `keep_previous` is set their frames were kept as well, and so are their
+    /// entries.
+    pub fn save(&self, path: &Path, keep_previous: bool) -> anyhow::Result<()> {
+        let mut entries = std::mem::take(&mut *self.current.lock().unwrap());
+
+        if keep_previous {
+            let rom_hashes = self.rom_hashes.lock().unwrap();
+            let not_ran = self
+                .previous
+                .iter()
+                .filter(|(rom_id, _)| !rom_hashes.contains_key(*rom_id));
+
+            for (rom_id, entry) in not_ran {
+                entries.entry(rom_id.clone()).or_insert_with(|| entry.clone());
+            }
+        }
+
+        let file = CacheFile {
+            emulator_version: self.emulator_version.clone(),
+            entries,
+        };
+
+        std::fs::write(path, serde_json::to_vec(&file)?).with_context(|| format!("Couldn't write run cache {path:?}"))
+    }
+}
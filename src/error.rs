@@ -0,0 +1,33 @@
+/// A failure of the run as a whole, as opposed to a failure of a single test.
+///
+/// Errors of individual tests, such as emulator panics or exceeding the per-test timeout, are instead part of the
+/// [TestReport](crate::TestReport).
+#[derive(Debug, thiserror::Error)]
+pub enum EmuRunnerError {
+    /// The [EmuRunnerOptions](crate::options::EmuRunnerOptions) failed validation.
+    #[error("Invalid runner options")]
+    InvalidOptions(#[source] anyhow::Error),
+    /// Multiple tests share the same `rom_id`, listed here, which would make them overwrite each other's frames.
+    ///
+    /// This commonly happens when ROMs with the same file stem are found in different directories, which
+    /// [TestCandidate::with_relative_rom_id](crate::inputs::TestCandidate::with_relative_rom_id) avoids.
+    #[error("Multiple tests share the rom_id(s): {}", .0.join(", "))]
+    DuplicateRomIds(Vec<String>),
+    #[error("Couldn't build the thread pool")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    /// The output directory couldn't be prepared, or the state of the run couldn't be saved to it.
+    #[error("Couldn't set up the output directory")]
+    DirectorySetup(#[source] anyhow::Error),
+    /// The [EmuTestResultFormatter](crate::formatters::EmuTestResultFormatter) returned an error.
+    #[error("The formatter failed")]
+    Formatter(#[source] anyhow::Error),
+    /// The file system watcher of [EmuTestRunner::watch](crate::EmuTestRunner::watch) couldn't be set up.
+    #[cfg(feature = "watch")]
+    #[error("Couldn't watch the test files")]
+    Watch(#[source] anyhow::Error),
+    /// All tests ran, but some of them failed their snapshot comparison.
+    ///
+    /// Only returned by [EmuTestRunner::run_tests](crate::EmuTestRunner::run_tests).
+    #[error("There were {0} failed tests")]
+    TestsFailed(usize),
+}
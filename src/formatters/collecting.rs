@@ -0,0 +1,133 @@
+use crate::formatters::EmuTestResultFormatter;
+use crate::inputs::TestCandidate;
+use crate::outputs::{RunnerError, RunnerErrorKind, RunnerOutput, TestChanged, TestError, TestFailed, TestOutput};
+use crate::processing::TestReport;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Collects all results in memory instead of printing them, for asserting on the results of a run.
+///
+/// The formatter can be cheaply cloned, with all clones sharing the same results. Keep a clone around before passing
+/// one to the [EmuTestRunner](crate::EmuTestRunner) to inspect the results afterwards.
+///
+/// # Example
+/// ```no_run
+/// # use emu_test_runner::EmuTestRunner;
+/// # use emu_test_runner::formatters::collecting::CollectingFormatter;
+/// # use emu_test_runner::options::EmuRunnerOptions;
+/// # fn run(tests: Vec<emu_test_runner::inputs::TestCandidate>) -> anyhow::Result<()> {
+/// let formatter = CollectingFormatter::new();
+/// let runner = EmuTestRunner::new(Box::new(formatter.clone()), EmuRunnerOptions::default())?;
+/// runner.run_tests(tests.into_iter(), |_, _| vec![])?;
+///
+/// assert_eq!(formatter.failures().len(), 0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CollectingFormatter {
+    state: Arc<Mutex<CollectedResults>>,
+}
+
+#[derive(Debug, Default)]
+struct CollectedResults {
+    finished: Vec<FinishedTest>,
+    processed: Vec<TestOutput>,
+    report: Option<TestReport>,
+}
+
+/// A test which finished running, as passed to [EmuTestResultFormatter::handle_test_finish].
+#[derive(Debug, Clone)]
+pub struct FinishedTest {
+    pub candidate: Arc<TestCandidate>,
+    /// How many times the emulator was ran, `None` if the test errored.
+    pub attempts: Option<usize>,
+    /// The kind of error the test ran into, if any.
+    pub error_kind: Option<RunnerErrorKind>,
+    /// The full description of the error the test ran into, if any.
+    pub error: Option<String>,
+}
+
+impl CollectingFormatter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All tests which finished running so far, in the order in which they finished.
+    pub fn finished(&self) -> Vec<FinishedTest> {
+        self.state.lock().unwrap().finished.clone()
+    }
+
+    /// All sub-tests which were compared to their snapshot so far, in the order in which they were processed.
+    pub fn processed(&self) -> Vec<TestOutput> {
+        self.state.lock().unwrap().processed.clone()
+    }
+
+    /// The final report, `None` if the run hasn't completed yet.
+    pub fn report(&self) -> Option<TestReport> {
+        self.state.lock().unwrap().report.clone()
+    }
+
+    /// The failures of the final report, empty if the run hasn't completed yet.
+    pub fn failures(&self) -> Vec<TestFailed> {
+        self.report().map(|report| report.fails).unwrap_or_default()
+    }
+
+    /// The errors of the final report, empty if the run hasn't completed yet.
+    pub fn errors(&self) -> Vec<TestError> {
+        self.report().map(|report| report.errors).unwrap_or_default()
+    }
+
+    /// The changes of the final report, empty if the run hasn't completed yet.
+    pub fn changed(&self) -> Vec<TestChanged> {
+        self.report().map(|report| report.changed).unwrap_or_default()
+    }
+}
+
+impl EmuTestResultFormatter for CollectingFormatter {
+    fn handle_start(&self, _test_count: usize) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.finished.clear();
+        state.processed.clear();
+        state.report = None;
+
+        Ok(())
+    }
+
+    fn handle_test_start(&self, _test: &TestCandidate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_finish(&self, test_complete: Result<&RunnerOutput, &RunnerError>) -> anyhow::Result<()> {
+        let finished = match test_complete {
+            Ok(output) => FinishedTest {
+                candidate: output.candidate.clone(),
+                attempts: Some(output.context.attempts),
+                error_kind: None,
+                error: None,
+            },
+            Err(error) => FinishedTest {
+                candidate: error.candidate.clone(),
+                attempts: None,
+                error_kind: Some(error.context.kind),
+                error: Some(format!("{:#}", error.context.error)),
+            },
+        };
+
+        self.state.lock().unwrap().finished.push(finished);
+
+        Ok(())
+    }
+
+    fn handle_test_processed(&self, output: &TestOutput) -> anyhow::Result<()> {
+        self.state.lock().unwrap().processed.push(output.clone());
+
+        Ok(())
+    }
+
+    fn handle_complete(&self, report: &TestReport, _time_taken: Duration) -> anyhow::Result<()> {
+        self.state.lock().unwrap().report = Some(report.clone());
+
+        Ok(())
+    }
+}
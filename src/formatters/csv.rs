@@ -0,0 +1,83 @@
+use crate::formatters::EmuTestResultFormatter;
+use crate::inputs::TestCandidate;
+use crate::outputs::{RunnerError, RunnerOutput};
+use crate::processing::TestReport;
+use anyhow::Context;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Writes the timing of every sub-test to a CSV file once all tests have completed, for tracking performance over
+/// time.
+///
+/// The file has the columns `rom_id,tag,outcome,duration_ms`, in that order. The `tag` is empty for frames without one,
+/// and the `duration_ms` is empty for tests which errored before they could be timed.
+pub struct CsvTimingFormatter {
+    output_file: PathBuf,
+}
+
+impl CsvTimingFormatter {
+    /// Create a new formatter which will write its CSV to `output_file`.
+    pub fn new(output_file: impl Into<PathBuf>) -> Self {
+        Self {
+            output_file: output_file.into(),
+        }
+    }
+}
+
+impl EmuTestResultFormatter for CsvTimingFormatter {
+    fn handle_start(&self, _test_count: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_start(&self, _test: &TestCandidate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_finish(&self, _test_complete: Result<&RunnerOutput, &RunnerError>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_complete(&self, report: &TestReport, _time_taken: Duration) -> anyhow::Result<()> {
+        let mut csv = String::from("rom_id,tag,outcome,duration_ms\n");
+
+        for output in &report.test_outputs {
+            let tag = output
+                .context
+                .frame
+                .as_ref()
+                .and_then(|frame| frame.tag.as_deref())
+                .unwrap_or_default();
+            let duration_ms = output
+                .context
+                .time_taken
+                .map(|time| format!("{:.3}", time.as_secs_f64() * 1000.0))
+                .unwrap_or_default();
+
+            writeln!(
+                csv,
+                "{},{},{},{}",
+                escape_csv(&output.candidate.rom_id),
+                escape_csv(tag),
+                output.context.output.name(),
+                duration_ms
+            )?;
+        }
+
+        if let Some(parent) = self.output_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(&self.output_file, csv)
+            .with_context(|| format!("Couldn't write CSV timings to {:?}", self.output_file))
+    }
+}
+
+/// Quote the `field` if it contains a character with a special meaning in CSV.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,234 @@
+use crate::formatters::EmuTestResultFormatter;
+use crate::inputs::TestCandidate;
+use crate::outputs::{RunnerError, RunnerOutput};
+use crate::processing::TestReport;
+use anyhow::Context;
+use base64::Engine;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Writes a single, self-contained HTML file once all tests have completed.
+///
+/// Failures and changes are shown with their relevant images side by side, all images are embedded in the file itself
+/// so it can be freely moved around or archived. Formats which browsers can't display, such as
+/// [OutputImageFormat::Qoi](crate::options::OutputImageFormat::Qoi), are only referred to by their path.
+pub struct HtmlFormatter {
+    output_file: PathBuf,
+}
+
+impl HtmlFormatter {
+    /// Create a new formatter which will write its report to `output_file`, e.g. `./test_output/report.html`.
+    pub fn new(output_file: impl Into<PathBuf>) -> Self {
+        Self {
+            output_file: output_file.into(),
+        }
+    }
+}
+
+impl EmuTestResultFormatter for HtmlFormatter {
+    fn handle_start(&self, _test_count: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_start(&self, _test: &TestCandidate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_finish(&self, _test_complete: Result<&RunnerOutput, &RunnerError>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_complete(&self, report: &TestReport, time_taken: Duration) -> anyhow::Result<()> {
+        let mut html = String::new();
+
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(
+            html,
+            "<html><head><meta charset=\"utf-8\"><title>Emulator Test Report</title>"
+        )?;
+        writeln!(html, "<style>{STYLE}</style></head><body>")?;
+        writeln!(
+            html,
+            "<h1>Ran {} Test-cases in {:.2?} (Out of {} Tests)</h1>",
+            report.test_outputs.len(),
+            time_taken,
+            report.original_tests_count
+        )?;
+        writeln!(
+            html,
+            "<p>Passed: {} | Same: {} | Changed: {} | Failed: {} | Died: {}</p>",
+            report.passed.len(),
+            report.unchanged.len(),
+            report.changed.len(),
+            report.fails.len(),
+            report.errors.len()
+        )?;
+        if !report.no_snapshot.is_empty() {
+            writeln!(html, "<p>No snapshot: {}</p>", report.no_snapshot.len())?;
+        }
+
+        let tally = report.candidate_tally();
+        if tally.total != report.test_outputs.len() {
+            writeln!(
+                html,
+                "<p>Per test: Passed: {} | Same: {} | Changed: {} | Failed: {} | Died: {}</p>",
+                tally.passed, tally.unchanged, tally.changed, tally.failed, tally.errored
+            )?;
+        }
+
+        if !report.errors.is_empty() {
+            writeln!(html, "<h2>Errors</h2>")?;
+
+            for error in &report.errors {
+                write_test_header(&mut html, &error.candidate)?;
+                writeln!(
+                    html,
+                    "<pre>{}</pre>",
+                    escape_html(&format!("{:#}", error.context.reason))
+                )?;
+            }
+        }
+
+        if !report.fails.is_empty() {
+            writeln!(html, "<h2>Failures</h2>")?;
+
+            for fail in &report.fails {
+                let output = &fail.context.output;
+                write_test_header(&mut html, &fail.candidate)?;
+
+                let mut images = vec![("Was", output.failure_path.as_path())];
+                images.extend(output.old_path.as_deref().map(|old_path| ("Old", old_path)));
+                images.push(("Expected", &output.snapshot_path));
+                write_images(&mut html, &images)?;
+            }
+        }
+
+        if !report.changed.is_empty() {
+            writeln!(html, "<h2>Changes</h2>")?;
+
+            for change in &report.changed {
+                write_test_header(&mut html, &change.candidate)?;
+                write_images(
+                    &mut html,
+                    &[
+                        ("Old", &change.context.output.old_path),
+                        ("New", &change.context.output.changed_path),
+                    ],
+                )?;
+            }
+        }
+
+        writeln!(html, "</body></html>")?;
+
+        if let Some(parent) = self.output_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(&self.output_file, html)
+            .with_context(|| format!("Couldn't write HTML report to {:?}", self.output_file))
+    }
+}
+
+const STYLE: &str = "body { font-family: sans-serif; } \
+    .images { display: flex; gap: 1em; } \
+    figure { margin: 0; } \
+    img { image-rendering: pixelated; min-width: 240px; border: 1px solid #888; } \
+    .missing { padding: 2em; border: 1px dashed #888; color: #888; } \
+    pre { min-width: 240px; padding: 0.5em; border: 1px solid #888; }";
+
+fn write_test_header(html: &mut String, candidate: &TestCandidate) -> anyhow::Result<()> {
+    writeln!(
+        html,
+        "<h3>{} <small>({})</small></h3>",
+        escape_html(&candidate.rom_id),
+        escape_html(&candidate.rom_path.to_string_lossy())
+    )?;
+
+    Ok(())
+}
+
+fn write_images(html: &mut String, images: &[(&str, &Path)]) -> anyhow::Result<()> {
+    writeln!(html, "<div class=\"images\">")?;
+
+    for (caption, path) in images {
+        writeln!(html, "<figure>")?;
+
+        match std::fs::read(path) {
+            // Text snapshots are shown as is.
+            Ok(data) if path.extension().is_some_and(|ext| ext == "txt") => {
+                writeln!(html, "<pre>{}</pre>", escape_html(&String::from_utf8_lossy(&data)))?
+            }
+            Ok(data) => match mime_type(path) {
+                Some(mime_type) => writeln!(
+                    html,
+                    "<img src=\"data:{mime_type};base64,{}\" alt=\"{}\">",
+                    base64::engine::general_purpose::STANDARD.encode(data),
+                    escape_html(&path.to_string_lossy())
+                )?,
+                None => writeln!(
+                    html,
+                    "<div class=\"missing\">Image can't be shown: {}</div>",
+                    escape_html(&path.to_string_lossy())
+                )?,
+            },
+            Err(_) => writeln!(
+                html,
+                "<div class=\"missing\">Image missing: {}</div>",
+                escape_html(&path.to_string_lossy())
+            )?,
+        }
+
+        writeln!(html, "<figcaption>{caption}</figcaption></figure>")?;
+    }
+
+    writeln!(html, "</div>")?;
+
+    Ok(())
+}
+
+/// The MIME type of the image at `path`, `None` if browsers can't display it, such as QOI or raw frames.
+fn mime_type(path: &Path) -> Option<&'static str> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => Some("image/png"),
+        Some("bmp") => Some("image/bmp"),
+        Some("gif") => Some("image/gif"),
+        Some("webp") => Some("image/webp"),
+        _ => None,
+    }
+}
+
+fn escape_html(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_frames_are_not_embedded_as_images() {
+        let path = std::env::temp_dir().join(format!("emu_test_runner_html_{}.bin", std::process::id()));
+        std::fs::write(&path, [0u8; 16]).unwrap();
+
+        let mut html = String::new();
+        write_images(&mut html, &[("Was", &path)]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!html.contains("<img"), "{html}");
+        assert!(html.contains("can't be shown"), "{html}");
+    }
+}
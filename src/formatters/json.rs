@@ -0,0 +1,89 @@
+use crate::formatters::EmuTestResultFormatter;
+use crate::inputs::TestCandidate;
+use crate::outputs::{RunnerError, RunnerOutput, TestOutput};
+use crate::processing::{RunSummary, TestReport};
+use anyhow::Context;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The version of the JSON schema written by [JsonFormatter], bumped whenever a breaking change is made.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Writes the final report as a JSON document once all tests have completed.
+///
+/// The document contains a [RunSummary] as well as one entry for every sub-test, which includes the `rom_id`,
+/// `rom_path`, `time_taken`, `outcome` and any outcome specific paths.
+pub struct JsonFormatter {
+    output_file: PathBuf,
+}
+
+#[derive(serde::Serialize)]
+struct JsonReport<'a> {
+    schema_version: u32,
+    summary: RunSummary,
+    tests: &'a [TestOutput],
+}
+
+impl JsonFormatter {
+    /// Create a new formatter which will write its report to `output_file`.
+    pub fn new(output_file: impl Into<PathBuf>) -> Self {
+        Self {
+            output_file: output_file.into(),
+        }
+    }
+}
+
+impl EmuTestResultFormatter for JsonFormatter {
+    fn handle_start(&self, _test_count: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_start(&self, _test: &TestCandidate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_finish(&self, _test_complete: Result<&RunnerOutput, &RunnerError>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_complete(&self, report: &TestReport, time_taken: Duration) -> anyhow::Result<()> {
+        let json_report = JsonReport {
+            schema_version: JSON_SCHEMA_VERSION,
+            summary: report.summary(time_taken),
+            tests: &report.test_outputs,
+        };
+
+        if let Some(parent) = self.output_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = std::fs::File::create(&self.output_file)
+            .with_context(|| format!("Couldn't create JSON report at {:?}", self.output_file))?;
+
+        let mut writer = std::io::BufWriter::new(file);
+
+        serde_json::to_writer_pretty(&mut writer, &json_report)?;
+        // Dropping the writer would silently discard a failure to write the last buffered part of the report.
+        writer
+            .flush()
+            .with_context(|| format!("Couldn't write JSON report to {:?}", self.output_file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn failing_to_write_the_report_is_an_error() {
+        let report = TestReport::new(0, Vec::new(), false);
+
+        // Every write to `/dev/full` fails, which only surfaces once the buffered report is flushed.
+        assert!(JsonFormatter::new("/dev/full")
+            .handle_complete(&report, Duration::ZERO)
+            .is_err());
+        assert!(report.write_manifest("/dev/full").is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::formatters::EmuTestResultFormatter;
+use crate::inputs::TestCandidate;
+use crate::outputs::{RunnerError, RunnerOutput, TestOutputType};
+use crate::processing::TestReport;
+use anyhow::Context;
+use fxhash::FxHashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Writes a JUnit compatible XML report once all tests have completed, for consumption by CI systems.
+///
+/// Every sub-test becomes a `<testcase>`, with failures and errors mapped to `<failure>` and `<error>` elements
+/// respectively. The sub-tests of a test with several of them are told apart by the tag of their frame, or otherwise their
+/// index, in brackets after the `rom_id`. Changed sub-tests are only reported as failures if
+/// [TestReport::treat_changes_as_failure] is set.
+pub struct JUnitFormatter {
+    output_file: PathBuf,
+    suite_name: String,
+}
+
+impl JUnitFormatter {
+    /// Create a new formatter which will write its report to `output_file`.
+    pub fn new(output_file: impl Into<PathBuf>) -> Self {
+        Self {
+            output_file: output_file.into(),
+            suite_name: "emu_test_runner".to_string(),
+        }
+    }
+
+    /// Set the `name` attribute of the resulting `<testsuite>`.
+    pub fn with_suite_name(mut self, name: impl Into<String>) -> Self {
+        self.suite_name = name.into();
+        self
+    }
+}
+
+impl EmuTestResultFormatter for JUnitFormatter {
+    fn handle_start(&self, _test_count: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_start(&self, _test: &TestCandidate) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_test_finish(&self, _test_complete: Result<&RunnerOutput, &RunnerError>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn handle_complete(&self, report: &TestReport, time_taken: Duration) -> anyhow::Result<()> {
+        let mut xml = String::new();
+
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        let changed_failures = if report.treat_changes_as_failure { report.changed.len() } else { 0 };
+        writeln!(
+            xml,
+            r#"<testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+            escape_xml(&self.suite_name),
+            report.test_outputs.len(),
+            report.fails.len() + changed_failures,
+            report.errors.len(),
+            report.no_snapshot.len(),
+            time_taken.as_secs_f64()
+        )?;
+
+        // The sub-tests of a single test are adjacent in the report.
+        let mut sub_test_counts = FxHashMap::default();
+        for output in &report.test_outputs {
+            *sub_test_counts.entry(&output.candidate.rom_id).or_insert(0usize) += 1;
+        }
+        let mut previous_rom_id = None;
+        let mut sub_test_index = 0;
+
+        for output in &report.test_outputs {
+            let rom_id = &output.candidate.rom_id;
+            sub_test_index = if previous_rom_id == Some(rom_id) { sub_test_index + 1 } else { 0 };
+            previous_rom_id = Some(rom_id);
+
+            let name = if sub_test_counts[rom_id] > 1 {
+                let tag = output.context.frame.as_ref().and_then(|frame| frame.tag.as_deref());
+                match tag {
+                    Some(tag) => format!("{rom_id} [{tag}]"),
+                    None => format!("{rom_id} [{sub_test_index}]"),
+                }
+            } else {
+                rom_id.clone()
+            };
+            let time = output.context.time_taken.unwrap_or_default();
+            write!(
+                xml,
+                r#"  <testcase name="{}" classname="{}" time="{:.3}""#,
+                escape_xml(&name),
+                escape_xml(&output.candidate.rom_path.to_string_lossy()),
+                time.as_secs_f64()
+            )?;
+
+            match &output.context.output {
+                TestOutputType::Unchanged(_) | TestOutputType::Passed(_) => writeln!(xml, "/>")?,
+                TestOutputType::NoSnapshot(_) => {
+                    writeln!(xml, ">")?;
+                    writeln!(xml, r#"    <skipped message="No snapshot to compare to"/>"#)?;
+                    writeln!(xml, "  </testcase>")?;
+                }
+                TestOutputType::Changed(changed) if report.treat_changes_as_failure => {
+                    let message = format!("Changed compared to the previous run: {:?}", changed.changed_path);
+                    writeln!(xml, ">")?;
+                    writeln!(
+                        xml,
+                        r#"    <failure message="{0}" type="Changed">{0}</failure>"#,
+                        escape_xml(&message)
+                    )?;
+                    writeln!(xml, "  </testcase>")?;
+                }
+                TestOutputType::Changed(changed) => {
+                    writeln!(xml, ">")?;
+                    writeln!(
+                        xml,
+                        "    <system-out>Changed: {}</system-out>",
+                        escape_xml(&changed.changed_path.to_string_lossy())
+                    )?;
+                    writeln!(xml, "  </testcase>")?;
+                }
+                TestOutputType::Failure(failure) => {
+                    let mut message = format!(
+                        "Failed snapshot test, {}. Was: {:?}, Expected: {:?}",
+                        failure.kind, failure.failure_path, failure.snapshot_path
+                    );
+                    if failure.snapshots_tried > 1 {
+                        message += &format!(" (or any of {} other snapshots)", failure.snapshots_tried - 1);
+                    }
+                    writeln!(xml, ">")?;
+                    writeln!(
+                        xml,
+                        r#"    <failure message="{0}" type="SnapshotMismatch">{0}</failure>"#,
+                        escape_xml(&message)
+                    )?;
+                    writeln!(xml, "  </testcase>")?;
+                }
+                TestOutputType::Error(error) => {
+                    writeln!(xml, ">")?;
+                    writeln!(
+                        xml,
+                        r#"    <error message="{}" type="Error">{}</error>"#,
+                        escape_xml(&error.reason.to_string()),
+                        escape_xml(&format!("{:#}", error.reason))
+                    )?;
+                    writeln!(xml, "  </testcase>")?;
+                }
+            }
+        }
+
+        writeln!(xml, "</testsuite>")?;
+
+        if let Some(parent) = self.output_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(&self.output_file, xml)
+            .with_context(|| format!("Couldn't write JUnit report to {:?}", self.output_file))
+    }
+}
+
+fn escape_xml(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::EmuRunnerOptions;
+    use crate::outputs::{FrameOutput, RgbaFrame};
+    use crate::{panics, EmuTestRunner};
+
+    #[test]
+    fn sequence_frames_get_distinct_testcase_names() {
+        let _lock = panics::hook_lock();
+        let dir = std::env::temp_dir().join(format!("emu_test_runner_junit_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("sequence.gb");
+        std::fs::write(&rom_path, b"rom").unwrap();
+        let report_path = dir.join("junit.xml");
+
+        let options = EmuRunnerOptions {
+            output_path: dir.join("output"),
+            snapshot_path: dir.join("snapshots"),
+            expected_frame_width: 2,
+            expected_frame_height: 2,
+            // Without a snapshot every sub-test is then skipped.
+            snapshot_only: true,
+            ..Default::default()
+        };
+        let runner = EmuTestRunner::new(Box::new(JUnitFormatter::new(&report_path)), options).unwrap();
+        runner
+            .run_tests_with_report(std::iter::once(TestCandidate::new("sequence", &rom_path)), |_, _| {
+                vec![
+                    FrameOutput {
+                        tag: None,
+                        frame: RgbaFrame(vec![0; 2 * 2 * 4]),
+                    };
+                    3
+                ]
+            })
+            .unwrap();
+        let xml = std::fs::read_to_string(&report_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(xml.contains(r#"skipped="3""#), "{xml}");
+        for index in 0..3 {
+            assert!(xml.contains(&format!(r#"name="sequence [{index}]""#)), "{xml}");
+        }
+    }
+}
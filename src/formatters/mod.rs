@@ -3,9 +3,16 @@ use std::time::Duration;
 use crate::inputs::TestCandidate;
 pub use indicatif;
 
-use crate::outputs::{RunnerError, RunnerOutput};
-use crate::processing::TestReport;
+use crate::outputs::{RunnerError, RunnerOutput, TestOutput};
+/// The report types passed to [EmuTestResultFormatter::handle_complete], re-exported so formatter implementations can
+/// import everything they need from this module.
+pub use crate::processing::{RunSummary, TestReport};
 
+pub mod collecting;
+pub mod csv;
+pub mod html;
+pub mod json;
+pub mod junit;
 pub mod simple;
 
 pub trait EmuTestResultFormatter {
@@ -14,6 +21,9 @@ pub trait EmuTestResultFormatter {
 
     /// Called whenever a test is about to start executing
     ///
+    /// Every call is followed by exactly one call to [EmuTestResultFormatter::handle_test_finish] for the same test, on
+    /// the same thread.
+    ///
     /// Note that this can be called from several threads at the same time.
     fn handle_test_start(&self, test: &TestCandidate) -> anyhow::Result<()>;
 
@@ -24,11 +34,31 @@ pub trait EmuTestResultFormatter {
     /// Can be used to show a progress bar if desired.
     fn handle_test_finish(&self, test_complete: Result<&RunnerOutput, &RunnerError>) -> anyhow::Result<()>;
 
+    /// Called whenever a sub-test has been compared to its snapshot, with its final verdict.
+    ///
+    /// Unlike [EmuTestResultFormatter::handle_test_finish] this knows whether the test passed, which allows pass/fail
+    /// counters to be updated as the results come in. Frames are only compared once all tests have finished running, so
+    /// all calls to this follow the last call to [EmuTestResultFormatter::handle_test_finish].
+    ///
+    /// Note that this can be called from several threads at the same time. Does nothing by default.
+    fn handle_test_processed(&self, _output: &TestOutput) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called when the run ran into a problem which it could recover from, such as an output directory which couldn't be
+    /// set up, but which might still affect the results.
+    ///
+    /// Prints the warning to `stderr` by default.
+    fn handle_warning(&self, warning: &str) -> anyhow::Result<()> {
+        eprintln!("Warning: {warning}");
+        Ok(())
+    }
+
     /// Handle the final report, containing all tests and the results thereof.
     ///
     /// # Arguments
     /// * `report` - The report containing all the categories of test results
     /// * `time_taken` - The time it took for the *entire* test suite to run from a user perspective.
-    /// Individual tests also have a `time_taken` variable for that particular test.
+    ///   Individual tests also have a `time_taken` variable for that particular test.
     fn handle_complete(&self, report: &TestReport, time_taken: Duration) -> anyhow::Result<()>;
 }
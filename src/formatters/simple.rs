@@ -1,17 +1,56 @@
 use crate::formatters::EmuTestResultFormatter;
 use crate::inputs::TestCandidate;
-use crate::outputs::{RunnerError, RunnerOutput};
+use crate::outputs::{FailureKind, RunnerError, RunnerOutput, TestOutput, TestOutputType};
 use crate::processing::TestReport;
 use indicatif::{ProgressBar, ProgressStyle};
 use owo_colors::{CssColors, OwoColorize};
 use std::collections::HashSet;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-#[derive(Default)]
+/// The progress bar template used by [SimpleConsoleFormatter::with_progress].
+///
+/// The `{eta}` is based on indicatif's smoothed estimate of the time per test, which copes reasonably well with the highly
+/// variable duration of individual tests.
+pub const DEFAULT_PROGRESS_TEMPLATE: &str = "{wide_bar} {pos}/{len} ({per_sec}, ETA {eta}) - {msg}";
+
+/// How much [SimpleConsoleFormatter] prints once all tests have completed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only print the summary counts.
+    Quiet,
+    /// Print the summary counts, and the details of every error, failure, and change.
+    #[default]
+    Normal,
+    /// Like [Verbosity::Normal], but also list every passed and unchanged test with its duration, and the tests which
+    /// lack a snapshot.
+    Verbose,
+}
+
 pub struct SimpleConsoleFormatter {
     progress: Option<indicatif::ProgressBar>,
     current_tests: Arc<Mutex<HashSet<String>>>,
+    tally: ProgressTally,
+    verbosity: Verbosity,
+    slowest_tests: usize,
+    most_memory_tests: usize,
+    github_annotations: bool,
+}
+
+impl Default for SimpleConsoleFormatter {
+    fn default() -> Self {
+        Self {
+            progress: None,
+            current_tests: Default::default(),
+            tally: Default::default(),
+            verbosity: Verbosity::default(),
+            slowest_tests: 5,
+            most_memory_tests: 5,
+            github_annotations: std::env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true"),
+        }
+    }
 }
 
 impl SimpleConsoleFormatter {
@@ -24,18 +63,63 @@ impl SimpleConsoleFormatter {
         self
     }
 
-    pub fn with_progress(mut self, total_tests: u64) -> Self {
-        self.progress = Some(
-            indicatif::ProgressBar::new(total_tests)
-                .with_style(ProgressStyle::with_template("{wide_bar} {pos}/{len} - {msg}").expect("Invalid template")),
-        );
+    /// Set how much is printed once all tests have completed, [Verbosity::Normal] by default.
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
         self
     }
+
+    /// Set how many of the slowest tests are listed after the summary, `5` by default. `0` omits the list entirely.
+    ///
+    /// Not shown with [Verbosity::Quiet].
+    pub fn with_slowest_tests(mut self, count: usize) -> Self {
+        self.slowest_tests = count;
+        self
+    }
+
+    /// Set how many of the tests with the highest peak memory usage are listed after the summary, `5` by default.
+    ///
+    /// Only shown if [EmuRunnerOptions::measure_memory](crate::options::EmuRunnerOptions::measure_memory) is enabled,
+    /// and not with [Verbosity::Quiet].
+    pub fn with_most_memory_tests(mut self, count: usize) -> Self {
+        self.most_memory_tests = count;
+        self
+    }
+
+    /// Print a GitHub Actions `::error` annotation for every failure and error, which GitHub shows inline on the ROM.
+    ///
+    /// Enabled by default when running in GitHub Actions, as detected through the `GITHUB_ACTIONS` environment variable.
+    pub fn with_github_annotations(mut self, enabled: bool) -> Self {
+        self.github_annotations = enabled;
+        self
+    }
+
+    /// Show a progress bar using the [DEFAULT_PROGRESS_TEMPLATE].
+    pub fn with_progress(self, total_tests: u64) -> Self {
+        self.with_progress_template(total_tests, DEFAULT_PROGRESS_TEMPLATE)
+            .expect("Invalid template")
+    }
+
+    /// Show a progress bar using a custom [indicatif template](indicatif::style::ProgressStyle#templates).
+    ///
+    /// The `{msg}` key contains the tests which are currently running, followed by the amount of sub-tests with each
+    /// outcome so far, such as `✔12 ❌2`.
+    pub fn with_progress_template(mut self, total_tests: u64, template: &str) -> anyhow::Result<Self> {
+        self.progress =
+            Some(indicatif::ProgressBar::new(total_tests).with_style(ProgressStyle::with_template(template)?));
+        Ok(self)
+    }
 }
 
 impl EmuTestResultFormatter for SimpleConsoleFormatter {
     fn handle_start(&self, test_count: usize) -> anyhow::Result<()> {
-        println!("=== Running {} Snapshot Tests ===\n", test_count.green());
+        if test_count == 0 {
+            println!("=== No Snapshot Tests found ===\n");
+        } else {
+            println!("=== Running {} Snapshot Tests ===\n", test_count.green());
+        }
+
+        self.tally.reset();
         Ok(())
     }
 
@@ -66,11 +150,286 @@ impl EmuTestResultFormatter for SimpleConsoleFormatter {
         Ok(())
     }
 
+    fn handle_test_processed(&self, output: &TestOutput) -> anyhow::Result<()> {
+        if let Some(progress) = self.progress.as_ref() {
+            self.tally.add(&output.context.output);
+
+            let lock = self.current_tests.lock().unwrap();
+            self.update_progress_message(progress, lock.iter());
+        }
+
+        Ok(())
+    }
+
+    fn handle_warning(&self, warning: &str) -> anyhow::Result<()> {
+        let print = || eprintln!("{} {warning}", "Warning:".yellow());
+
+        match self.progress.as_ref() {
+            Some(progress) => progress.suspend(print),
+            None => print(),
+        }
+
+        Ok(())
+    }
+
     fn handle_complete(&self, report: &TestReport, time_taken: Duration) -> anyhow::Result<()> {
         if let Some(progress) = self.progress.as_ref() {
             progress.finish_and_clear()
         }
 
+        if self.verbosity >= Verbosity::Verbose {
+            self.print_successes(report);
+        }
+
+        if self.verbosity >= Verbosity::Normal {
+            self.print_details(report);
+        }
+
+        // Final Report
+        println!(
+            "=== Report - Ran {} Test-cases in {:.2?} (Out of {} Tests) ===",
+            report.test_outputs.len().green(),
+            time_taken.purple(),
+            report.original_tests_count.green()
+        );
+
+        let newly_passing = report.passed.iter().filter(|p| p.context.output.is_new).count();
+        print_count_line(
+            "✔ Passed:",
+            16,
+            report.passed.len().green(),
+            (newly_passing > 0).then(|| format!("{} newly passing", newly_passing.bright_green())),
+        );
+
+        let new_tests = report.unchanged.iter().filter(|p| p.context.output.newly_added).count();
+        print_count_line(
+            "😴 Same:",
+            15,
+            report.unchanged.len().green(),
+            (new_tests > 0).then(|| format!("{} new tests", new_tests.green())),
+        );
+
+        let changed_len = report.changed.len();
+        print_count_line(
+            "🔀 Changed:",
+            15,
+            changed_len.color(count_colour(changed_len, CssColors::RebeccaPurple)),
+            None,
+        );
+
+        let failed_len = report.fails.len();
+        let new_fails = report.fails.iter().filter(|p| p.context.output.is_new).count();
+        print_count_line(
+            "❌ Failed:",
+            15,
+            failed_len.color(count_colour(failed_len, CssColors::Red)),
+            (new_fails > 0).then(|| format!("{} new fails", new_fails.red())),
+        );
+
+        let errors_len = report.errors.len();
+        print_count_line(
+            "💀 Died:",
+            15,
+            errors_len.color(count_colour(errors_len, CssColors::Red)),
+            None,
+        );
+
+        // Only possible with `snapshot_only`, and thus left out otherwise.
+        if !report.no_snapshot.is_empty() {
+            print_count_line("❔ No snapshot:", 15, report.no_snapshot.len().yellow(), None);
+        }
+
+        // The counts above are per frame, which only differs from the counts per test if there are sequence tests.
+        let tally = report.candidate_tally();
+        if tally.total != report.test_outputs.len() {
+            println!(
+                "Per test: {} passed, {} same, {} changed, {} failed, {} died (Out of {} Tests with {} frames)",
+                tally.passed.green(),
+                tally.unchanged.green(),
+                tally
+                    .changed
+                    .color(count_colour(tally.changed, CssColors::RebeccaPurple)),
+                tally.failed.color(count_colour(tally.failed, CssColors::Red)),
+                tally.errored.color(count_colour(tally.errored, CssColors::Red)),
+                tally.total.green(),
+                report.test_outputs.len().green(),
+            );
+
+            if tally.no_snapshot > 0 {
+                println!("Per test: {} without a snapshot", tally.no_snapshot.yellow());
+            }
+        }
+
+        if self.verbosity >= Verbosity::Normal {
+            self.print_slowest(report);
+            self.print_most_memory(report);
+        }
+
+        if self.github_annotations {
+            self.print_github_annotations(report);
+        }
+
+        Ok(())
+    }
+}
+
+impl SimpleConsoleFormatter {
+    fn update_progress_message<'a>(&self, progress: &ProgressBar, set: impl Iterator<Item = &'a String>) {
+        let message = set.take(3).map(|s| &**s).collect::<Vec<&str>>().join(",");
+        let running = (!message.is_empty()).then(|| format!("Running: {}...", truncate_to_char_boundary(&message, 10)));
+        // Sub-tests are only compared once all tests have ran, so the tally only starts counting once nothing is running.
+        let tally = self.tally.message();
+
+        progress.set_message(match (running, tally) {
+            (Some(running), Some(tally)) => format!("{running} {tally}"),
+            (running, tally) => running.or(tally).unwrap_or_default(),
+        });
+    }
+
+    /// Print every passed and unchanged test, along with its duration.
+    fn print_successes(&self, report: &TestReport) {
+        if !report.passed.is_empty() {
+            println!("{}\n", "== Passed ==".on_green());
+
+            for pass in &report.passed {
+                println!(
+                    "= {}({:?}) = {:.2?}",
+                    pass.candidate.rom_id.green(),
+                    pass.candidate.rom_path,
+                    pass.context.time_taken.unwrap_or_default()
+                );
+            }
+
+            println!()
+        }
+
+        if !report.unchanged.is_empty() {
+            println!("{}\n", "== Unchanged ==".on_color(CssColors::Gray));
+
+            for same in &report.unchanged {
+                println!(
+                    "= {}({:?}) = {:.2?}",
+                    same.candidate.rom_id.color(CssColors::Gray),
+                    same.candidate.rom_path,
+                    same.context.time_taken.unwrap_or_default()
+                );
+            }
+
+            println!()
+        }
+
+        let coverage = report.snapshot_coverage();
+        if !coverage.uncovered.is_empty() || !coverage.partial.is_empty() {
+            println!("{}\n", "== Missing Snapshots ==".on_color(CssColors::Gray));
+
+            for candidate in &coverage.uncovered {
+                println!(
+                    "= {}({:?}) =",
+                    candidate.rom_id.color(CssColors::Gray),
+                    candidate.rom_path
+                );
+            }
+
+            for candidate in &coverage.partial {
+                println!(
+                    "= {}({:?}) = (some frames)",
+                    candidate.rom_id.color(CssColors::Gray),
+                    candidate.rom_path
+                );
+            }
+
+            println!()
+        }
+    }
+
+    /// Print the slowest tests, excluding those which errored before they could be timed.
+    fn print_slowest(&self, report: &TestReport) {
+        let mut seen = HashSet::new();
+        let mut timings = report
+            .test_outputs
+            .iter()
+            .filter_map(|output| Some((&output.candidate.rom_id, output.context.time_taken?)))
+            // All sub-tests of a sequence test share the same timing
+            .filter(|(rom_id, _)| seen.insert(*rom_id))
+            .collect::<Vec<_>>();
+
+        if self.slowest_tests == 0 || timings.is_empty() {
+            return;
+        }
+
+        timings.sort_by(|(_, left), (_, right)| right.cmp(left));
+
+        println!("\n=== Slowest {} Tests ===", self.slowest_tests.min(timings.len()));
+
+        for (rom_id, time_taken) in timings.into_iter().take(self.slowest_tests) {
+            println!("{: <30} {:.2?}", rom_id, time_taken.purple());
+        }
+
+        if let Some(percentiles) = report.timing_percentiles() {
+            println!(
+                "p50: {:.2?} | p90: {:.2?} | p99: {:.2?} | max: {:.2?}",
+                percentiles.p50.purple(),
+                percentiles.p90.purple(),
+                percentiles.p99.purple(),
+                percentiles.max.purple()
+            );
+        }
+    }
+
+    /// Print the tests with the highest peak memory usage, if it was measured.
+    fn print_most_memory(&self, report: &TestReport) {
+        let mut seen = HashSet::new();
+        let mut usages = report
+            .test_outputs
+            .iter()
+            .filter_map(|output| Some((&output.candidate.rom_id, output.context.peak_memory?)))
+            // All sub-tests of a sequence test share the same measurement
+            .filter(|(rom_id, _)| seen.insert(*rom_id))
+            .collect::<Vec<_>>();
+
+        if self.most_memory_tests == 0 || usages.is_empty() {
+            return;
+        }
+
+        usages.sort_by(|(_, left), (_, right)| right.cmp(left));
+
+        println!(
+            "\n=== Most Memory Hungry {} Tests ===",
+            self.most_memory_tests.min(usages.len())
+        );
+
+        for (rom_id, peak_memory) in usages.into_iter().take(self.most_memory_tests) {
+            let mebibytes = format!("{:.1} MiB", peak_memory as f64 / (1024.0 * 1024.0));
+            println!("{: <30} {}", rom_id, mebibytes.purple());
+        }
+    }
+
+    /// Print a GitHub Actions workflow command for every failure and error, see
+    /// [SimpleConsoleFormatter::with_github_annotations].
+    fn print_github_annotations(&self, report: &TestReport) {
+        for fail in &report.fails {
+            let message = format!(
+                "Failed snapshot test, {}.\nWas: {:?}\nExpected: {:?}",
+                fail.context.output.kind, fail.context.output.failure_path, fail.context.output.snapshot_path
+            );
+            print_github_error(
+                &fail.candidate.rom_path.to_string_lossy(),
+                &fail.candidate.rom_id,
+                &message,
+            );
+        }
+
+        for error in &report.errors {
+            print_github_error(
+                &error.candidate.rom_path.to_string_lossy(),
+                &error.candidate.rom_id,
+                &format!("{:#}", error.context.reason),
+            );
+        }
+    }
+
+    /// Print the details of every error, failure, and change.
+    fn print_details(&self, report: &TestReport) {
         if !report.errors.is_empty() {
             println!("{}", "== Found errors ==".on_red());
 
@@ -93,6 +452,19 @@ impl EmuTestResultFormatter for SimpleConsoleFormatter {
                 println!("Failed snapshot test",);
                 println!("Was: {:?}", fail.context.output.failure_path);
                 println!("Expected: {:?}", fail.context.output.snapshot_path);
+                if fail.context.output.snapshots_tried > 1 {
+                    println!(
+                        "Matched none of the {} acceptable snapshots",
+                        fail.context.output.snapshots_tried
+                    );
+                }
+                println!("Reason: {}", fail.context.output.kind);
+
+                if let FailureKind::Text { expected, actual } = &fail.context.output.kind {
+                    println!("Expected text:\n{expected}");
+                    println!("Actual text:\n{actual}");
+                }
+
                 println!()
             }
         }
@@ -110,83 +482,125 @@ impl EmuTestResultFormatter for SimpleConsoleFormatter {
                 println!()
             }
         }
+    }
+}
 
-        let changed_len = report.changed.len();
-        let failed_len = report.fails.len();
-        let errors_len = report.errors.len();
+/// Print a single GitHub Actions `::error` workflow command.
+fn print_github_error(file: &str, title: &str, message: &str) {
+    println!(
+        "::error file={},title={}::{}",
+        escape_github_property(file),
+        escape_github_property(title),
+        escape_github_data(message)
+    );
+}
 
-        // Final Report
-        println!(
-            "=== Report - Ran {} Test-cases in {:.2?} (Out of {} Tests) ===",
-            report.test_outputs.len().green(),
-            time_taken.purple(),
-            report.original_tests_count.green()
-        );
+fn escape_github_data(input: &str) -> String {
+    input.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
 
-        let newly_passing = report.passed.iter().filter(|p| p.context.output.is_new).count();
-        if newly_passing > 0 {
-            println!(
-                "{: <16} {} ({} newly passing)",
-                "✔ Passed:",
-                report.passed.len().green(),
-                newly_passing.bright_green()
-            );
-        } else {
-            println!("{: <16} {}", "✔ Passed:", report.passed.len().green());
-        }
+fn escape_github_property(input: &str) -> String {
+    escape_github_data(input).replace(':', "%3A").replace(',', "%2C")
+}
 
-        let new_tests = report.unchanged.iter().filter(|p| p.context.output.newly_added).count();
-        if new_tests > 0 {
-            println!(
-                "{: <15} {} ({} new tests)",
-                "😴 Same:",
-                report.unchanged.len().green(),
-                new_tests.green()
-            );
-        } else {
-            println!("{: <15} {}", "😴 Same:", report.unchanged.len().green());
+/// Print a single line of the final summary, with an optional remark in parentheses.
+///
+/// The `width` of the `label` differs per line, as some emoji are rendered wider than others.
+fn print_count_line(label: &str, width: usize, count: impl Display, remark: Option<String>) {
+    match remark {
+        Some(remark) => println!("{label: <width$} {count} ({remark})"),
+        None => println!("{label: <width$} {count}"),
+    }
+}
+
+/// The colour to show a count of non-passing tests in, grayed out if there are none.
+fn count_colour(count: usize, colour: CssColors) -> CssColors {
+    if count == 0 {
+        CssColors::Gray
+    } else {
+        colour
+    }
+}
+
+/// The amount of sub-tests with each outcome so far, shown in the progress bar.
+#[derive(Default)]
+struct ProgressTally {
+    ok: AtomicUsize,
+    changed: AtomicUsize,
+    failed: AtomicUsize,
+    errored: AtomicUsize,
+    no_snapshot: AtomicUsize,
+}
+
+impl ProgressTally {
+    fn add(&self, output: &TestOutputType) {
+        let counter = match output {
+            TestOutputType::Passed(_) | TestOutputType::Unchanged(_) => &self.ok,
+            TestOutputType::Changed(_) => &self.changed,
+            TestOutputType::Failure(_) => &self.failed,
+            TestOutputType::Error(_) => &self.errored,
+            TestOutputType::NoSnapshot(_) => &self.no_snapshot,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for counter in [&self.ok, &self.changed, &self.failed, &self.errored, &self.no_snapshot] {
+            counter.store(0, Ordering::Relaxed);
         }
+    }
 
-        println!(
-            "{: <15} {}",
-            "🔀 Changed:",
-            if report.changed.is_empty() {
-                0.color(CssColors::Gray)
-            } else {
-                changed_len.color(CssColors::RebeccaPurple)
-            }
-        );
+    /// For example `✔12 ❌2`, where changed, errored, and sub-tests without a snapshot are only mentioned once there
+    /// are any.
+    ///
+    /// `None` if nothing has been counted yet.
+    fn message(&self) -> Option<String> {
+        let [ok, changed, failed, errored, no_snapshot] =
+            [&self.ok, &self.changed, &self.failed, &self.errored, &self.no_snapshot]
+                .map(|counter| counter.load(Ordering::Relaxed));
 
-        let new_fails = report.fails.iter().filter(|p| p.context.output.is_new).count();
-        if new_fails > 0 {
-            println!(
-                "{: <15} {} ({} new fails)",
-                "❌ Failed:",
-                if report.fails.is_empty() { 0.color(CssColors::Gray) } else { failed_len.color(CssColors::Red) },
-                new_fails.red()
-            );
-        } else {
-            println!(
-                "{: <15} {}",
-                "❌ Failed:",
-                if report.fails.is_empty() { 0.color(CssColors::Gray) } else { failed_len.color(CssColors::Red) }
-            );
+        if ok + changed + failed + errored + no_snapshot == 0 {
+            return None;
         }
 
-        println!(
-            "{: <15} {}",
-            "💀 Died:",
-            if report.errors.is_empty() { 0.color(CssColors::Gray) } else { errors_len.color(CssColors::Red) }
-        );
+        let mut message = format!("✔{ok} ❌{failed}");
+        if changed > 0 {
+            message.push_str(&format!(" 🔀{changed}"));
+        }
+        if errored > 0 {
+            message.push_str(&format!(" 💀{errored}"));
+        }
+        if no_snapshot > 0 {
+            message.push_str(&format!(" ❔{no_snapshot}"));
+        }
 
-        Ok(())
+        Some(message)
     }
 }
 
-impl SimpleConsoleFormatter {
-    fn update_progress_message<'a>(&self, progress: &ProgressBar, set: impl Iterator<Item = &'a String>) {
-        let message = set.take(3).map(|s| &**s).collect::<Vec<&str>>().join(",");
+/// Truncate `input` to at most `max_bytes`, without splitting a multibyte character.
+fn truncate_to_char_boundary(input: &str, max_bytes: usize) -> &str {
+    let end = input
+        .char_indices()
+        .map(|(index, c)| index + c.len_utf8())
+        .take_while(|&end| end <= max_bytes)
+        .last()
+        .unwrap_or(0);
+
+    &input[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        progress.set_message(format!("Running: {}...", &message[..(message.len().min(10))]));
+    #[test]
+    fn truncation_never_splits_a_multibyte_character() {
+        // Every `é` is two bytes, so a limit of 3 falls inside the second character.
+        assert_eq!(truncate_to_char_boundary("ééééé", 3), "é");
+        assert_eq!(truncate_to_char_boundary("ééééé", 4), "éé");
+        assert_eq!(truncate_to_char_boundary("ééééé", 1), "");
+        assert_eq!(truncate_to_char_boundary("abc", 10), "abc");
     }
 }
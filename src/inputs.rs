@@ -1,10 +1,55 @@
+use anyhow::Context;
+use globset::{Glob, GlobSetBuilder};
 use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+use crate::options::PixelTolerance;
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TestCandidate {
+    /// Identifies the test in reports, and names its frames on disk.
+    ///
+    /// Any characters which aren't safe to use in a file name are escaped in the latter, see
+    /// [sanitize_file_name](crate::sanitize_file_name).
     pub rom_id: String,
     pub rom_path: PathBuf,
+    /// The in-memory ROM to pass to the emulator.
+    ///
+    /// If present `rom_path` is no longer read, and only used to identify this test.
+    #[serde(skip)]
+    pub rom_data: Option<RomData>,
+    /// Whether this test case will produce multiple snapshots. These snapshots will be compared individually
+    /// (e.g, can have a mix of expected/non-expected snapshots).
+    ///
+    /// Tests producing more than one frame are always treated as sequence tests, this flag ensures a test is treated as
+    /// such even if it happens to produce a single frame.
+    pub is_sequence_test: bool,
+    /// The tolerance to use when comparing this test to its snapshots.
+    ///
+    /// Overrides [EmuRunnerOptions::tolerance](crate::options::EmuRunnerOptions::tolerance) when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<PixelTolerance>,
+    /// Free-form labels used to categorise tests, for example to only run a subset of them through a [TagFilter].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// A seed for any randomness in the emulator, such as the initial contents of RAM, to make the test reproducible.
+    ///
+    /// The runner doesn't use this itself, it's up to `emu_run` to pass it on to the emulator. Defaults to a value
+    /// derived from the `rom_id`, which stays the same across runs and platforms, see [TestCandidate::with_seed] to
+    /// override it.
+    pub seed: u64,
+}
+
+/// The contents of a ROM, cheaply shared between clones of a [TestCandidate].
+#[derive(Clone)]
+pub struct RomData(pub Arc<[u8]>);
+
+impl Debug for RomData {
+    fn fmt(&self, f: &mut Formatter) -> ::core::fmt::Result {
+        Formatter::debug_tuple(f, "RomData").field(&self.0.len()).finish()
+    }
 }
 
 impl TestCandidate {
@@ -12,15 +57,115 @@ impl TestCandidate {
     ///
     /// # Arguments
     /// * `id` should be unique, and the path should point to a ROM that can be loaded by the emulator under test.
-    /// * `is_sequence_test` should be `true` if this test case will produce multiple snapshots. These snapshots will be
-    /// compared individually (e.g, can have a mix of expected/non-expected snapshots).
     pub fn new(id: impl Into<String>, path: impl Into<PathBuf>) -> TestCandidate {
+        let rom_id = id.into();
+
         Self {
-            rom_id: id.into(),
+            seed: seed_from_rom_id(&rom_id),
+            rom_id,
             rom_path: path.into(),
+            rom_data: None,
+            is_sequence_test: false,
+            tolerance: None,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Mark whether this test case will produce multiple snapshots, see [TestCandidate::is_sequence_test].
+    pub fn with_sequence_test(mut self, is_sequence_test: bool) -> Self {
+        self.is_sequence_test = is_sequence_test;
+        self
+    }
+
+    /// Supply the ROM directly instead of reading it from `rom_path`.
+    ///
+    /// Useful for ROMs which are generated in memory, or fetched from elsewhere.
+    pub fn with_rom_data(mut self, data: impl Into<Arc<[u8]>>) -> Self {
+        self.rom_data = Some(RomData(data.into()));
+        self
+    }
+
+    /// Use a custom tolerance for this test, overriding the suite-wide default.
+    ///
+    /// Useful for the odd test which is inherently noisier than the rest of the suite.
+    pub fn with_tolerance(mut self, tolerance: PixelTolerance) -> Self {
+        self.tolerance = Some(tolerance);
+        self
+    }
+
+    /// Use the given `seed` instead of the one derived from the `rom_id`, see [TestCandidate::seed].
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Add a tag to this test, see [TestCandidate::tags].
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Add the name of the directory containing the ROM as a tag.
+    ///
+    /// Useful in combination with the discovery functions, where a ROM in `test_roms/cpu/` would be tagged with `cpu`.
+    pub fn with_parent_directory_tag(self) -> Self {
+        let parent = self
+            .rom_path
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .map(|name| name.to_string_lossy().into_owned());
+
+        match parent {
+            Some(parent) => self.with_tag(parent),
+            None => self,
         }
     }
 
+    /// Derive the `rom_id` from the path of the ROM relative to `root`, instead of just its file stem.
+    ///
+    /// The `rom_id` is the relative path itself, including the extension and with `/` as separator on all platforms.
+    /// With a `root` of `test_roms` the ROMs `test_roms/cpu/nop.gb` and `test_roms/ppu/nop.gb` thus become `cpu/nop.gb`
+    /// and `ppu/nop.gb`, rather than both being `nop`. As distinct paths always result in distinct `rom_id`s, this also
+    /// keeps `nop.gb` and `nop.gbc` apart. The separators are escaped in the file names of the frames, see
+    /// [sanitize_file_name](crate::sanitize_file_name). Useful in combination with the discovery functions, which search
+    /// sub-directories as well.
+    ///
+    /// The `rom_id` is left unchanged if the ROM isn't inside `root`. A [TestCandidate::seed] derived from the old
+    /// `rom_id` is derived from the new one instead, while a custom seed is kept.
+    ///
+    /// # Example
+    /// ```
+    /// # use emu_test_runner::inputs::TestCandidate;
+    /// let test = TestCandidate::new("nop", "test_roms/cpu/nop.gb").with_relative_rom_id("test_roms");
+    ///
+    /// assert_eq!(test.rom_id, "cpu/nop.gb");
+    /// ```
+    pub fn with_relative_rom_id(mut self, root: impl AsRef<Path>) -> Self {
+        let Ok(relative) = self.rom_path.strip_prefix(root) else {
+            return self;
+        };
+        if relative.file_name().is_none() {
+            return self;
+        }
+
+        let rom_id = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if self.seed == seed_from_rom_id(&self.rom_id) {
+            self.seed = seed_from_rom_id(&rom_id);
+        }
+        self.rom_id = rom_id;
+        self
+    }
+
+    /// Whether this test has the given `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
     /// Find all possible test candidates in a directory and all its sub-directories based on a given file extension.
     ///
     /// Note that this will assume that there are *no* sequence tests.
@@ -28,26 +173,225 @@ impl TestCandidate {
         path: impl AsRef<Path>,
         extension: impl AsRef<str>,
     ) -> anyhow::Result<Vec<TestCandidate>> {
-        let files = list_files_with_extensions(path.as_ref(), extension.as_ref())?;
+        Self::find_all_in_directory_multi(path, &[extension.as_ref()])
+    }
 
-        Ok(files
+    /// Find all possible test candidates in a directory and all its sub-directories which have any of the given file
+    /// `extensions`. The candidates are sorted by their path.
+    ///
+    /// Note that this will assume that there are *no* sequence tests.
+    pub fn find_all_in_directory_multi(
+        path: impl AsRef<Path>,
+        extensions: &[&str],
+    ) -> anyhow::Result<Vec<TestCandidate>> {
+        let files = list_files_with_extensions_multi(path.as_ref(), extensions)?;
+
+        files
             .into_iter()
-            .map(|path| TestCandidate::new(get_rom_fs_id(&path).into_owned(), path))
-            .collect())
+            .map(|path| Ok(TestCandidate::new(get_rom_fs_id(&path)?.into_owned(), path)))
+            .collect()
+    }
+
+    /// Find all sequence tests in a directory, where every immediate sub-directory is a single sequence test.
+    ///
+    /// The `rom_id` of each test is the name of its sub-directory, and its ROM is the first file (sorted by path) with
+    /// the given `extension` in said sub-directory. Sub-directories without such a file are skipped.
+    ///
+    /// For these candidates `emu_run` is expected to return one [FrameOutput](crate::outputs::FrameOutput) for every
+    /// point in the sequence that should be compared, in order. Each frame should have a unique `tag`, which is used to
+    /// name its image. All images of a single sequence test are put in a sub-folder named after its `rom_id` if
+    /// [EmuRunnerOptions::put_sequence_tests_in_subfolder](crate::options::EmuRunnerOptions::put_sequence_tests_in_subfolder)
+    /// is set.
+    pub fn find_sequence_tests_in_directory(
+        path: impl AsRef<Path>,
+        extension: impl AsRef<str>,
+    ) -> anyhow::Result<Vec<TestCandidate>> {
+        let mut directories = std::fs::read_dir(path.as_ref())?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        directories.retain(|path| path.is_dir());
+        directories.sort();
+
+        let mut result = Vec::with_capacity(directories.len());
+
+        for directory in directories {
+            let Some(rom_path) = list_files_with_extensions(&directory, extension.as_ref())?
+                .into_iter()
+                .next()
+            else {
+                continue;
+            };
+            let rom_id = directory
+                .file_name()
+                .context("Sequence test directory has no name")?
+                .to_string_lossy()
+                .into_owned();
+
+            result.push(TestCandidate::new(rom_id, rom_path).with_sequence_test(true));
+        }
+
+        Ok(result)
+    }
+
+    /// Find all test candidates in a directory and all its sub-directories whose path matches the given glob patterns.
+    ///
+    /// Patterns are matched against the path relative to `path`, and patterns starting with `!` exclude any path they
+    /// match. A file is included if it matches at least one including pattern, and none of the excluding patterns.
+    ///
+    /// Note that this will assume that there are *no* sequence tests.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use emu_test_runner::inputs::TestCandidate;
+    /// let tests = TestCandidate::find_all_matching("./test_roms", &["**/*.gb", "**/*.gbc", "!broken/**"]);
+    /// ```
+    pub fn find_all_matching(path: impl AsRef<Path>, glob_patterns: &[&str]) -> anyhow::Result<Vec<TestCandidate>> {
+        let root = path.as_ref();
+        let mut includes = GlobSetBuilder::new();
+        let mut excludes = GlobSetBuilder::new();
+
+        for pattern in glob_patterns {
+            let (builder, glob) = match pattern.strip_prefix('!') {
+                Some(excluded) => (&mut excludes, excluded),
+                None => (&mut includes, *pattern),
+            };
+
+            builder.add(Glob::new(glob).with_context(|| format!("Invalid glob pattern `{pattern}`"))?);
+        }
+
+        let (includes, excludes) = (includes.build()?, excludes.build()?);
+        let files = list_files_matching(root, &|file| {
+            let relative = file.strip_prefix(root).unwrap_or(file);
+            includes.is_match(relative) && !excludes.is_match(relative)
+        })?;
+
+        files
+            .into_iter()
+            .map(|path| Ok(TestCandidate::new(get_rom_fs_id(&path)?.into_owned(), path)))
+            .collect()
+    }
+}
+
+/// Selects tests based on their [TestCandidate::tags], for use with
+/// [EmuTestRunner::run_tests_filtered](crate::EmuTestRunner::run_tests_filtered).
+///
+/// A test matches if it has at least one of the included tags (or no tags are included at all), and none of the
+/// excluded tags.
+///
+/// # Example
+/// ```
+/// # use emu_test_runner::inputs::{TagFilter, TestCandidate};
+/// let filter = TagFilter::default().include("cpu").exclude("slow");
+///
+/// assert!(filter.matches(&TestCandidate::new("add", "add.gb").with_tag("cpu")));
+/// assert!(!filter.matches(&TestCandidate::new("div", "div.gb").with_tag("cpu").with_tag("slow")));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl TagFilter {
+    /// Only select tests with this tag, or any of the other included tags.
+    pub fn include(mut self, tag: impl Into<String>) -> Self {
+        self.include.push(tag.into());
+        self
+    }
+
+    /// Never select tests with this tag.
+    pub fn exclude(mut self, tag: impl Into<String>) -> Self {
+        self.exclude.push(tag.into());
+        self
+    }
+
+    pub fn matches(&self, candidate: &TestCandidate) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|tag| candidate.has_tag(tag));
+
+        included && !self.exclude.iter().any(|tag| candidate.has_tag(tag))
     }
 }
 
 /// Lists all files in the provided `path` (if the former is a directory) with the provided
-/// `extension`. Will traverse all sub-directories in search of this extension
+/// `extension`. Will traverse all sub-directories in search of this extension.
+///
+/// The `extension` can be given with or without its leading dot, and is matched regardless of ASCII case. Thus both
+/// `gb` and `.gb` find `cpu_instrs.gb` as well as `CPU_INSTRS.GB`, but not `cpu_instrs.notgb`. An empty `extension`
+/// lists every file.
 pub fn list_files_with_extensions(path: impl AsRef<Path>, extension: impl AsRef<str>) -> anyhow::Result<Vec<PathBuf>> {
+    list_files_with_extensions_multi(path, &[extension.as_ref()])
+}
+
+/// Lists all files in the provided `path` (if the former is a directory) with any of the provided
+/// `extensions`. Will traverse all sub-directories in search of these extensions.
+///
+/// Extensions are matched like in [list_files_with_extensions]. The result is sorted by path, to ensure a consistent order across runs and machines.
+pub fn list_files_with_extensions_multi(path: impl AsRef<Path>, extensions: &[&str]) -> anyhow::Result<Vec<PathBuf>> {
+    list_files_matching(path.as_ref(), &|file| {
+        extensions.iter().any(|extension| {
+            let matches_all = extension.strip_prefix('.').unwrap_or(extension).is_empty();
+            matches_all || has_extension(file, extension)
+        })
+    })
+}
+
+/// Whether the file name of `path` ends in the given `extension`, ignoring ASCII case.
+///
+/// The `extension` may start with a dot, and may span multiple components such as `gb.zip`. It has to be preceded by a
+/// dot in the file name, and can't be the entire file name, so `gb` matches neither `foo.notgb` nor a file named `.gb`.
+/// An empty `extension` (or just `.`) never matches.
+///
+/// ```
+/// # use std::path::Path;
+/// # use emu_test_runner::inputs::has_extension;
+/// assert!(has_extension(Path::new("roms/cpu_instrs.gb"), "gb"));
+/// assert!(has_extension(Path::new("roms/CPU_INSTRS.GB"), ".gb"));
+/// assert!(has_extension(Path::new("roms/cpu_instrs.gb.zip"), "gb.zip"));
+///
+/// assert!(!has_extension(Path::new("roms/thing.notgb"), "gb"));
+/// assert!(!has_extension(Path::new("roms/bar.gb.bak"), "gb"));
+/// assert!(!has_extension(Path::new("roms/.gb"), "gb"));
+/// assert!(!has_extension(Path::new("roms/cpu_instrs.gb"), ""));
+/// ```
+pub fn has_extension(path: &Path, extension: &str) -> bool {
+    let extension = extension.strip_prefix('.').unwrap_or(extension);
+    if extension.is_empty() {
+        return false;
+    }
+
+    let Some(file_name) = path.file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+    let Some(split) = file_name.len().checked_sub(extension.len() + 1) else {
+        return false;
+    };
+
+    match (file_name.get(..split), file_name.get(split..)) {
+        (Some(stem), Some(suffix)) => {
+            !stem.is_empty() && suffix.starts_with('.') && suffix[1..].eq_ignore_ascii_case(extension)
+        }
+        _ => false,
+    }
+}
+
+/// Lists all files in the provided `path` (if the former is a directory) for which `filter` returns `true`.
+/// Will traverse all sub-directories.
+///
+/// Directory entries are visited in sorted order, as the order of [std::fs::read_dir] is platform dependent. The
+/// result is therefore sorted by path.
+fn list_files_matching(path: &Path, filter: &dyn Fn(&Path) -> bool) -> anyhow::Result<Vec<PathBuf>> {
     let mut result = Vec::with_capacity(40);
 
-    if path.as_ref().is_dir() {
-        for entry in std::fs::read_dir(path)? {
-            let path = entry?.path();
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for path in entries {
             if path.is_dir() {
-                result.extend(list_files_with_extensions(&path, extension.as_ref())?);
-            } else if path.to_str().filter(|t| t.ends_with(extension.as_ref())).is_some() {
+                result.extend(list_files_matching(&path, filter)?);
+            } else if filter(&path) {
                 result.push(path);
             }
         }
@@ -56,6 +400,71 @@ pub fn list_files_with_extensions(path: impl AsRef<Path>, extension: impl AsRef<
     Ok(result)
 }
 
-pub fn get_rom_fs_id(path: &Path) -> Cow<'_, str> {
-    path.file_stem().expect("Failed to get rom stem").to_string_lossy()
+/// The default `rom_id` of the ROM at `path`, its file stem.
+///
+/// Non-UTF8 characters are replaced, see [std::ffi::OsStr::to_string_lossy]. Fails if the `path` has no file stem, such
+/// as a path ending in `..`.
+pub fn get_rom_fs_id(path: &Path) -> anyhow::Result<Cow<'_, str>> {
+    let stem = path
+        .file_stem()
+        .with_context(|| format!("ROM path {path:?} has no file stem"))?;
+
+    Ok(stem.to_string_lossy())
+}
+
+/// The `rom_id`s which are shared by more than one of the `candidates`, sorted and without repeats.
+pub(crate) fn duplicate_rom_ids(candidates: &[TestCandidate]) -> Vec<String> {
+    let mut rom_ids = candidates
+        .iter()
+        .map(|candidate| candidate.rom_id.as_str())
+        .collect::<Vec<_>>();
+    rom_ids.sort_unstable();
+
+    let mut duplicates = rom_ids
+        .windows(2)
+        .filter(|pair| pair[0] == pair[1])
+        .map(|pair| pair[0].to_string())
+        .collect::<Vec<_>>();
+    duplicates.dedup();
+
+    duplicates
+}
+
+/// The default [TestCandidate::seed] of a test, the first 8 bytes of the BLAKE3 hash of its `rom_id`.
+fn seed_from_rom_id(rom_id: &str) -> u64 {
+    let hash = blake3::hash(rom_id.as_bytes());
+
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup::rom_id_to_file_name;
+
+    #[test]
+    fn relative_rom_ids_dont_collide() {
+        let ids = ["roms/a_b/c.gb", "roms/a/b_c.gb", "roms/cpu/nop.gb", "roms/cpu/nop.gbc"]
+            .map(|path| TestCandidate::new("", path).with_relative_rom_id("roms").rom_id);
+
+        assert_eq!(ids, ["a_b/c.gb", "a/b_c.gb", "cpu/nop.gb", "cpu/nop.gbc"]);
+
+        let file_names = ids
+            .iter()
+            .map(|rom_id| rom_id_to_file_name(rom_id, None, "png"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            file_names.iter().collect::<std::collections::HashSet<_>>().len(),
+            ids.len()
+        );
+        assert!(file_names.iter().all(|name| !name.contains('/')));
+    }
+
+    #[test]
+    fn rom_paths_without_a_stem_are_rejected() {
+        assert!(get_rom_fs_id(Path::new("/")).is_err());
+        assert!(get_rom_fs_id(Path::new("..")).is_err());
+        assert_eq!(get_rom_fs_id(Path::new("roms/cpu_instrs.gb")).unwrap(), "cpu_instrs");
+    }
 }
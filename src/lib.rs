@@ -1,143 +1,494 @@
-use std::path::Path;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::backtrace::Backtrace;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use anyhow::Context;
-use image::{EncodableLayout, ImageBuffer, Rgba};
+use fxhash::FxHashMap;
+use image::codecs::png::{FilterType, PngEncoder};
+use image::{DynamicImage, ImageBuffer};
 use rayon::prelude::*;
 
-use processing::{PathDefinitions, TestReport};
-pub use setup::{changed_path, failures_path, new_path, old_path};
+pub use error::EmuRunnerError;
+/// Re-exported for implementing a comparator for [EmuTestRunner::with_comparator].
+pub use image;
+pub use processing::{
+    compare_output_dirs, ArtifactKind, CandidateOutcome, CandidateReport, CandidateTally, DirDiff, ManifestEntry,
+    RunSummary, SnapshotCoverage, TestReport, TimingPercentiles,
+};
+use processing::{FrameDiff, PathDefinitions};
+pub use setup::{changed_path, failures_path, new_path, old_path, sanitize_file_name};
 
+use crate::cache::{CachedSubTest, RunCache};
 use crate::formatters::EmuTestResultFormatter;
 use crate::inputs::TestCandidate;
-use crate::options::EmuRunnerOptions;
+use crate::options::{Baseline, EmuRunnerOptions, OutputImageFormat, PixelFormat, PixelTolerance, SequenceMode};
 use crate::outputs::{
-    EmuContext, FrameOutput, RunnerError, RunnerOutput, RunnerOutputContext, TestOutput, TestOutputChanged,
-    TestOutputContext, TestOutputError, TestOutputFailure, TestOutputPassed, TestOutputType, TestOutputUnchanged,
+    Comparison, EmuContext, EmulatorOutput, EmulatorPanic, FailureKind, FrameLocation, FrameOutput, RgbaFrame,
+    RunnerError, RunnerErrorContext, RunnerErrorKind, RunnerOutput, RunnerOutputContext, TestOutput, TestOutputChanged,
+    TestOutputContext, TestOutputError, TestOutputFailure, TestOutputNoSnapshot, TestOutputPassed, TestOutputType,
+    TestOutputUnchanged, TimeoutError,
 };
 
+mod cache;
+mod error;
 pub mod formatters;
 pub mod inputs;
+mod memory;
 pub mod options;
 pub mod outputs;
 mod panics;
 mod processing;
 mod setup;
+#[cfg(feature = "watch")]
+mod watch;
 
 pub struct EmuTestRunner {
     formatter: Box<dyn EmuTestResultFormatter + Send + Sync>,
     options: EmuRunnerOptions,
-    thread_pool: rayon::ThreadPool,
+    /// The pool used to run the tests, `None` if they run on rayon's global pool instead.
+    thread_pool: Option<rayon::ThreadPool>,
+    /// The pool used to process the produced frames, `None` if they're processed on the `thread_pool`.
+    processing_pool: Option<rayon::ThreadPool>,
+    /// The frames read for a [Baseline::GitRef], keyed by their git object name.
+    git_baseline_cache: Mutex<FxHashMap<String, Option<Arc<[u8]>>>>,
+    /// Decides whether a frame matches its snapshot, see [EmuTestRunner::with_comparator].
+    comparator: Option<Comparator>,
 }
 
+/// A custom comparison of a snapshot (the first argument) and a frame (the second argument).
+type Comparator = Box<dyn Fn(&DynamicImage, &DynamicImage) -> Comparison + Send + Sync>;
+
 impl EmuTestRunner {
-    /// Instantiate a new test runner with the given formatter and options.
+    /// Instantiate a new test runner with the given formatter and options, failing if the `options` are invalid.
     ///
-    /// Will create a new [rayon::ThreadPool] for executing the tests on.
+    /// Will create a new [rayon::ThreadPool] for executing the tests on unless [EmuRunnerOptions::use_global_pool] is
+    /// set, and a second one for processing their frames if [EmuRunnerOptions::processing_threads] is set.
     pub fn new(
         formatter: Box<dyn EmuTestResultFormatter + Send + Sync>,
         options: EmuRunnerOptions,
-    ) -> anyhow::Result<Self> {
-        let thread_pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(options.num_threads.get())
-            .build()?;
+    ) -> Result<Self, EmuRunnerError> {
+        options.validate().map_err(EmuRunnerError::InvalidOptions)?;
+
+        let thread_pool = (!options.use_global_pool)
+            .then(|| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(options.num_threads.get())
+                    .build()
+            })
+            .transpose()?;
+        let processing_pool = options
+            .processing_threads
+            .map(|threads| rayon::ThreadPoolBuilder::new().num_threads(threads.get()).build())
+            .transpose()?;
 
         Ok(Self {
             formatter,
             options,
             thread_pool,
+            processing_pool,
+            git_baseline_cache: Default::default(),
+            comparator: None,
         })
     }
 
+    /// Decide whether frames match their snapshot with the given `comparator`, instead of the built-in comparison.
+    ///
+    /// The `comparator` is passed a snapshot and the corresponding frame, in that order, and can implement any logic
+    /// such as a perceptual comparison or downscaling both images first. It replaces the built-in comparison and thus
+    /// the [tolerance](EmuRunnerOptions::tolerance), while the
+    /// [compare_region](EmuRunnerOptions::compare_region) and [ignore_masks](EmuRunnerOptions::ignore_masks) are still
+    /// applied to both images before they're passed to it. Frames which are identical according to their
+    /// [hash_sidecars](EmuRunnerOptions::hash_sidecars) always match, without calling the `comparator`.
+    ///
+    /// Only comparisons against snapshots use the `comparator`, changes compared to the `old` frames are still detected
+    /// with the built-in comparison.
+    pub fn with_comparator(
+        mut self,
+        comparator: impl Fn(&DynamicImage, &DynamicImage) -> Comparison + Send + Sync + 'static,
+    ) -> Self {
+        self.comparator = Some(Box::new(comparator));
+        self
+    }
+
     /// Run the given tests and pass the results to the `formatter`.
     ///
     /// Any panic that occurs during the test execution is caught and can be reported on by the `formatter`.
     ///
     /// # Arguments
     /// * `emu_run` - Expects a function which, given a test and the associated in-memory ROM, runs the given ROM and
-    /// returns [FrameOutput] data.  A test can produce multiple instances of [FrameOutput]. This marks the test as a `sequence` test.
-    /// This can be useful if you need to perform some inputs on your test rom, and want to periodically make `FrameOutputs` to
-    /// ensure the intermediate results look correct as well.
+    ///   returns [FrameOutput] data.  A test can produce multiple instances of [FrameOutput]. This marks the test as a `sequence` test.
+    ///   This can be useful if you need to perform some inputs on your test rom, and want to periodically make `FrameOutputs` to
+    ///   ensure the intermediate results look correct as well.
+    ///   Alternatively an [EmulatorOutput] can be returned, which carries additional `metadata` alongside the frames.
+    ///
+    ///   The function has to be `'static`, as a test with a deadline runs on its own thread. If either the
+    ///   [timeout](EmuRunnerOptions::timeout) (15 seconds by default) or the
+    ///   [per_test_timeout](EmuRunnerOptions::per_test_timeout) is set, every test is ran on a freshly spawned thread
+    ///   instead of the thread pool's worker, which is never joined. A test which misses its deadline is left running on
+    ///   that thread in the background. Without either timeout the tests run directly on the thread pool.
     ///
     /// # Returns
-    /// An error if any tests were marked as `failed`. Note that crashing tests *do not* by default count as such, and will
-    /// thus not return an error.
-    pub fn run_tests<F, I>(&self, tests: I, emu_run: F) -> anyhow::Result<()>
+    /// [EmuRunnerError::TestsFailed] if any tests were marked as `failed`. Note that crashing tests *do not* by default
+    /// count as such, and will thus not return an error.
+    ///
+    /// [EmuRunnerError::DuplicateRomIds] if multiple tests share a `rom_id`, in which case no tests are ran.
+    pub fn run_tests<F, R, I>(&self, tests: I, emu_run: F) -> Result<(), EmuRunnerError>
     where
-        F: Fn(&TestCandidate, Vec<u8>) -> Vec<FrameOutput> + Send + Sync + std::panic::RefUnwindSafe,
+        F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+        R: Into<EmulatorOutput>,
         I: ExactSizeIterator<Item = TestCandidate> + Send,
     {
-        if let Some(timeout) = self.options.timeout {
-            start_timeout_killer(timeout);
+        let report = self.run_tests_with_report(tests, emu_run)?;
+
+        if report.fails.is_empty() {
+            Ok(())
+        } else {
+            Err(EmuRunnerError::TestsFailed(report.fails.len()))
         }
+    }
+
+    /// Run the given tests and pass the results to the `formatter`, see [EmuTestRunner::run_tests] for more information.
+    ///
+    /// # Returns
+    /// The [TestReport] which was passed to the `formatter`. Unlike [EmuTestRunner::run_tests] failed tests do *not*
+    /// result in an error, the caller is expected to inspect the report instead.
+    pub fn run_tests_with_report<F, R, I>(&self, tests: I, emu_run: F) -> Result<TestReport, EmuRunnerError>
+    where
+        F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+        R: Into<EmulatorOutput>,
+        I: ExactSizeIterator<Item = TestCandidate> + Send,
+    {
+        self.run_tests_streaming(tests, emu_run, |_| {})
+    }
+
+    /// Run the given tests and pass every sub-test to `on_output` as soon as its outcome is known, see
+    /// [EmuTestRunner::run_tests_with_report] for more information.
+    ///
+    /// Unlike [EmuTestResultFormatter::handle_test_finish] the `on_output` callback receives the final verdict of a
+    /// sub-test, after it has been compared to its snapshot. Sub-tests are processed in parallel, so the order in which
+    /// `on_output` is called is nondeterministic, and it can be called from several threads at the same time.
+    pub fn run_tests_streaming<F, R, I>(
+        &self,
+        tests: I,
+        emu_run: F,
+        on_output: impl Fn(&TestOutput) + Send + Sync,
+    ) -> Result<TestReport, EmuRunnerError>
+    where
+        F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+        R: Into<EmulatorOutput>,
+        I: ExactSizeIterator<Item = TestCandidate> + Send,
+    {
+        self.run_tests_inner(tests, emu_run, on_output, false)
+    }
 
+    /// See [EmuTestRunner::run_tests_streaming].
+    ///
+    /// If `partial` is set the `tests` are only a subset of all tests, in which case the output of the previous run of
+    /// the other tests is kept in the `new` directory and the run cache, so they don't lose their baseline.
+    fn run_tests_inner<F, R, I>(
+        &self,
+        tests: I,
+        emu_run: F,
+        on_output: impl Fn(&TestOutput) + Send + Sync,
+        partial: bool,
+    ) -> Result<TestReport, EmuRunnerError>
+    where
+        F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+        R: Into<EmulatorOutput>,
+        I: ExactSizeIterator<Item = TestCandidate> + Send,
+    {
         let start = Instant::now();
+        let deadline = self.options.timeout.map(|limit| Deadline {
+            at: start + limit,
+            error: TimeoutError::Suite { limit },
+        });
+        let emu_run = Arc::new(emu_run);
+        // Tests sharing a `rom_id` would silently overwrite each other's output, so refuse to run them at all.
+        let tests = tests.collect::<Vec<_>>();
+        let duplicates = inputs::duplicate_rom_ids(&tests);
+        if !duplicates.is_empty() {
+            return Err(EmuRunnerError::DuplicateRomIds(duplicates));
+        }
+
         let test_len = tests.len();
-        self.formatter.handle_start(test_len)?;
+        self.formatter
+            .handle_start(test_len)
+            .map_err(EmuRunnerError::Formatter)?;
+
+        if test_len == 0 {
+            // Nothing to run, and no reason to rotate away the output of the previous run.
+            let report = TestReport::new(0, Vec::new(), self.options.treat_changes_as_failure);
+            self.formatter
+                .handle_complete(&report, start.elapsed())
+                .map_err(EmuRunnerError::Formatter)?;
+
+            return Ok(report);
+        }
 
+        let cache_path = self.options.output_path.join(cache::CACHE_FILE_NAME);
+        let cache = self
+            .options
+            .emulator_version
+            .as_deref()
+            .map(|version| RunCache::load(&cache_path, version));
+
+        // Set once a test fails with `fail_fast` enabled, after which no new tests are started.
+        let stop = AtomicBool::new(false);
         let frame_results = panics::run_in_custom_handler(|| {
-            self.thread_pool.install(|| {
+            install(self.thread_pool.as_ref(), || {
                 tests
-                    .par_bridge()
-                    .map(|candidate| {
-                        let _ = self.formatter.handle_test_start(&candidate);
+                    .into_par_iter()
+                    .filter_map(|candidate| {
+                        if stop.load(Ordering::Relaxed) {
+                            return None;
+                        }
+
+                        let result = self.run_test_in_panic_handler(candidate, &emu_run, deadline, cache.as_ref());
+                        if self.options.fail_fast && self.fails_fast(&result) {
+                            stop.store(true, Ordering::Relaxed);
+                        }
 
-                        self.run_test_in_panic_handler(candidate, &emu_run)
+                        Some(result)
                     })
                     .collect::<Vec<_>>()
             })
         });
 
-        // Prepare the output by deleting the old stuff.
-        setup::setup_output_directory(&self.options.output_path)?;
-        setup::setup_snapshot_directory(&self.options.snapshot_path)?;
+        if !self.options.dry_run {
+            // Prepare the output by deleting the old stuff.
+            let warnings = setup::setup_output_directory(
+                &self.options.output_path,
+                &self.options.output_layout,
+                self.options.rotate_output,
+            )
+            .map_err(EmuRunnerError::DirectorySetup)?;
+
+            for warning in warnings {
+                self.formatter
+                    .handle_warning(&warning)
+                    .map_err(EmuRunnerError::Formatter)?;
+            }
+
+            setup::setup_snapshot_directory(&self.options.snapshot_path).map_err(EmuRunnerError::DirectorySetup)?;
+        }
 
-        let test_results = self.thread_pool.install(|| {
+        let processing_pool = self.processing_pool.as_ref().or(self.thread_pool.as_ref());
+        let test_results = install(processing_pool, || {
             frame_results
                 .into_par_iter()
-                .flat_map(|runner_output| self.process_result(runner_output))
+                .flat_map(|runner_output| {
+                    let outputs = self.process_result(runner_output, cache.as_ref());
+                    for output in &outputs {
+                        let _ = self.formatter.handle_test_processed(output);
+                        on_output(output);
+                    }
+                    outputs
+                })
                 .collect()
         });
 
-        let report = TestReport::new(test_len, test_results);
+        if partial && self.options.rotate_output && !self.options.dry_run {
+            // Done after processing, so the frames of the tests which did run are never overwritten through a link.
+            if let Err(e) = setup::carry_forward_old_output(&self.options.output_path, &self.options.output_layout) {
+                self.formatter
+                    .handle_warning(&format!(
+                        "Couldn't keep the output of the tests which weren't ran: {e:#}"
+                    ))
+                    .map_err(EmuRunnerError::Formatter)?;
+            }
+        }
 
-        self.formatter.handle_complete(&report, start.elapsed())?;
+        if let (Some(cache), false) = (&cache, self.options.dry_run) {
+            cache
+                .save(&cache_path, partial)
+                .map_err(EmuRunnerError::DirectorySetup)?;
+        }
 
-        if report.fails.is_empty() {
-            Ok(())
-        } else {
-            anyhow::bail!("There were {} failed tests", report.fails.len());
+        let report = TestReport::new(test_len, test_results, self.options.treat_changes_as_failure);
+
+        self.formatter
+            .handle_complete(&report, start.elapsed())
+            .map_err(EmuRunnerError::Formatter)?;
+
+        Ok(report)
+    }
+
+    /// Run only the given tests which match the `filter`, see [EmuTestRunner::run_tests_with_report] for more information.
+    ///
+    /// Tests which don't match the `filter` are never passed to `emu_run`, nor do they count towards the test count
+    /// passed to the `formatter` or the [TestReport::original_tests_count].
+    ///
+    /// The output of the previous run of the filtered out tests is kept, so they still have a baseline in the next run.
+    /// This requires [EmuRunnerOptions::rotate_output], without it only the frames of the tests which ran end up in the
+    /// `new` directory.
+    pub fn run_tests_filtered<F, R, I>(
+        &self,
+        tests: I,
+        filter: impl Fn(&TestCandidate) -> bool,
+        emu_run: F,
+    ) -> Result<TestReport, EmuRunnerError>
+    where
+        F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+        R: Into<EmulatorOutput>,
+        I: IntoIterator<Item = TestCandidate>,
+    {
+        let tests = tests.into_iter().filter(|test| filter(test)).collect::<Vec<_>>();
+
+        self.run_tests_inner(tests.into_iter(), emu_run, |_| {}, true)
+    }
+
+    /// Promote the frames of all tests in the `report` matching the `filter` to snapshots.
+    ///
+    /// The frame in the `new` output directory is copied to the snapshot directory, overwriting any existing snapshot.
+    /// This can be used to, for example, bless all `changed` tests after a deliberate change in the emulator. If the
+    /// snapshot is a directory of acceptable snapshots the frame is instead added to it, under its own file name.
+    ///
+    /// # Returns
+    /// The amount of snapshots which were written.
+    pub fn accept_snapshots(&self, report: &TestReport, filter: impl Fn(&TestOutput) -> bool) -> anyhow::Result<usize> {
+        let mut accepted = 0;
+
+        for output in report.test_outputs.iter().filter(|output| filter(output)) {
+            let Some(location) = output.context.frame.as_ref() else {
+                continue;
+            };
+
+            let snapshot_path = match location.new_path.file_name() {
+                Some(file_name) if location.snapshot_path.is_dir() => location.snapshot_path.join(file_name),
+                _ => location.snapshot_path.clone(),
+            };
+
+            if let Some(parent) = snapshot_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::copy(&location.new_path, &snapshot_path)
+                .with_context(|| format!("Couldn't copy {:?} to snapshot {snapshot_path:?}", location.new_path))?;
+
+            let sidecar_path = processing::hash_sidecar_path(&location.new_path);
+            if sidecar_path.exists() {
+                std::fs::copy(&sidecar_path, processing::hash_sidecar_path(&snapshot_path))?;
+            }
+
+            accepted += 1;
         }
+
+        Ok(accepted)
     }
 
-    fn run_test_in_panic_handler<F>(&self, candidate: TestCandidate, emu_run: &F) -> Result<RunnerOutput, RunnerError>
+    /// Empty the `changed` and `failures` output directories, while keeping the frames in the `new` and `old` directories.
+    ///
+    /// Every run already starts by clearing these directories, so this is only needed to get rid of their contents in
+    /// between runs. For example, after [EmuTestRunner::accept_snapshots] made the changes of the last run obsolete.
+    pub fn reset_transient_dirs(&self) -> Result<(), EmuRunnerError> {
+        setup::clear_transient_directories(&self.options.output_path, &self.options.output_layout)
+            .map_err(EmuRunnerError::DirectorySetup)
+    }
+
+    fn run_test_in_panic_handler<F, R>(
+        &self,
+        candidate: TestCandidate,
+        emu_run: &Arc<F>,
+        deadline: Option<Deadline>,
+        cache: Option<&RunCache>,
+    ) -> Result<RunnerOutput, RunnerError>
     where
-        F: Fn(&TestCandidate, Vec<u8>) -> Vec<FrameOutput> + Send + Sync + std::panic::RefUnwindSafe,
+        F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+        R: Into<EmulatorOutput>,
     {
-        let runner_output = std::fs::read(&candidate.rom_path)
-            .context("Couldn't read ROM")
-            .and_then(|rom_data| {
-                let now = Instant::now();
+        let candidate = Arc::new(candidate);
+        let _ = self.formatter.handle_test_start(&candidate);
 
-                let frame = std::panic::catch_unwind(|| emu_run(&candidate, rom_data));
+        let rom_data = match &candidate.rom_data {
+            Some(data) => Ok(data.0.to_vec()),
+            None => std::fs::read(&candidate.rom_path)
+                .context("Couldn't read ROM")
+                .map_err(|error| RunnerErrorContext {
+                    kind: RunnerErrorKind::IoRead,
+                    error,
+                }),
+        };
 
-                let frame = match frame {
-                    Ok(frame) => Ok(frame),
-                    Err(_) => Err(anyhow::anyhow!(
-                        "Caught an emulator panic: `{}`",
-                        panics::latest_panic().unwrap()
-                    )),
-                }?;
+        let runner_output = rom_data.and_then(|rom_data| {
+            let now = Instant::now();
+            let cached_output = cache
+                .and_then(|cache| cache.lookup(&candidate.rom_id, &rom_data, candidate.seed))
+                .and_then(|entry| self.load_cached_output(entry));
 
-                Ok(RunnerOutput {
+            if let Some(output) = cached_output {
+                return Ok(RunnerOutput {
                     candidate: candidate.clone(),
                     context: RunnerOutputContext {
                         time_taken: now.elapsed(),
-                        frame_output: frame,
+                        frame_output: output.frames,
+                        metadata: Arc::new(output.metadata),
+                        text: output.text,
+                        attempts: 0,
+                        cached: true,
+                        peak_memory: None,
                     },
-                })
-            });
+                });
+            }
+
+            let mut attempts = 0;
+            let measure_memory = self.measures_memory();
+
+            loop {
+                attempts += 1;
+
+                let now = Instant::now();
+                let test_deadline = self.options.per_test_timeout.map(|limit| Deadline {
+                    at: now + limit,
+                    error: TimeoutError::Test { limit },
+                });
+
+                if measure_memory {
+                    memory::reset_peak_memory();
+                }
+
+                let frame = match Deadline::earliest(deadline, test_deadline) {
+                    Some(deadline) => run_emulator_watched(&candidate, rom_data.clone(), emu_run, deadline),
+                    None => run_emulator(&candidate, rom_data.clone(), emu_run.as_ref()),
+                };
+
+                match frame {
+                    Ok(mut frame) => {
+                        if let Some(preprocessor) = &self.options.frame_preprocessor {
+                            frame
+                                .frames
+                                .iter_mut()
+                                .for_each(|output| (preprocessor.0)(&mut output.frame));
+                        }
+
+                        break Ok(RunnerOutput {
+                            candidate: candidate.clone(),
+                            context: RunnerOutputContext {
+                                time_taken: now.elapsed(),
+                                frame_output: frame.frames,
+                                metadata: Arc::new(frame.metadata),
+                                text: frame.text,
+                                attempts,
+                                cached: false,
+                                peak_memory: measure_memory.then(memory::peak_memory).flatten(),
+                            },
+                        });
+                    }
+                    Err(e) => {
+                        let suite_timed_out = matches!(e.downcast_ref(), Some(TimeoutError::Suite { .. }));
+
+                        if suite_timed_out || attempts > self.options.retries {
+                            break Err(RunnerErrorContext {
+                                kind: RunnerErrorKind::of_emulator_error(&e),
+                                error: e,
+                            });
+                        }
+                    }
+                }
+            }
+        });
 
         let result = runner_output.map_err(|e| RunnerError { candidate, context: e });
 
@@ -146,93 +497,465 @@ impl EmuTestRunner {
         result
     }
 
-    fn process_result(&self, runner_output: Result<RunnerOutput, RunnerError>) -> Vec<TestOutput> {
-        let runner_output = match runner_output {
+    /// Whether the peak memory of tests should be measured, see [EmuRunnerOptions::measure_memory].
+    ///
+    /// The peak is tracked for the whole process, so it can only be attributed to a test if no others run alongside it.
+    fn measures_memory(&self) -> bool {
+        let test_threads = self
+            .thread_pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, rayon::ThreadPool::current_num_threads);
+
+        self.options.measure_memory && test_threads == 1
+    }
+
+    /// Whether the given result should stop the run when [EmuRunnerOptions::fail_fast] is set.
+    ///
+    /// The output directory is only prepared once all tests have ran, so the frames can't be processed yet. Instead they
+    /// are compared to just their snapshots, with any error along the way counting as a failure as well.
+    fn fails_fast(&self, result: &Result<RunnerOutput, RunnerError>) -> bool {
+        match result {
+            Ok(runner_output) => !matches!(self.matches_snapshots(runner_output), Ok(true)),
+            Err(_) => true,
+        }
+    }
+
+    /// Whether every frame, and the text, of the `runner_output` matches one of its snapshots (if it has any).
+    fn matches_snapshots(&self, runner_output: &RunnerOutput) -> anyhow::Result<bool> {
+        let frames = runner_output.context.frame_output.clone();
+        let tolerance = runner_output
+            .candidate
+            .tolerance
+            .as_ref()
+            .or(self.options.tolerance.as_ref());
+
+        for (frames, path_def, encoding) in self.frame_and_path_definitions(runner_output, frames) {
+            let snapshot_paths = path_def.existing_snapshot_paths()?;
+            if snapshot_paths.is_empty() {
+                continue;
+            }
+
+            let (image_frames, _) = self.prepare_frames(frames, encoding)?;
+            let mut matched = false;
+
+            for snapshot_path in snapshot_paths {
+                let snapshot_data = self.load_frames(&snapshot_path, encoding)?;
+
+                if self
+                    .compare_to_snapshot(&snapshot_data, &image_frames, tolerance)
+                    .is_none()
+                {
+                    matched = true;
+                    break;
+                }
+            }
+
+            if !matched {
+                return Ok(false);
+            }
+        }
+
+        if let Some(text) = &runner_output.context.text {
+            let snapshot_paths = self
+                .text_path_definitions(&runner_output.candidate.rom_id)
+                .existing_snapshot_paths()?;
+
+            for snapshot_path in &snapshot_paths {
+                if std::fs::read_to_string(snapshot_path)? == *text {
+                    return Ok(true);
+                }
+            }
+
+            return Ok(snapshot_paths.is_empty());
+        }
+
+        Ok(true)
+    }
+
+    fn process_result(
+        &self,
+        runner_output: Result<RunnerOutput, RunnerError>,
+        cache: Option<&RunCache>,
+    ) -> Vec<TestOutput> {
+        let mut runner_output = match runner_output {
             Ok(output) => output,
             Err(e) => {
                 return vec![e.owned_map(|error| TestOutputContext {
                     time_taken: None,
+                    frame: None,
+                    metadata: Default::default(),
+                    cached: false,
+                    peak_memory: None,
                     output: TestOutputType::Error(TestOutputError {
-                        reason: Arc::new(error),
+                        kind: Some(error.kind),
+                        reason: Arc::new(error.error),
                     }),
                 })]
             }
         };
 
         // Generate the path definitions for *all* the test's context frames.
-        self.frame_and_path_definitions(&runner_output)
-            .map(|(frame, path_def)| match self.process_frame(frame, path_def) {
-                Ok(output) => EmuContext {
-                    candidate: runner_output.candidate.clone(),
-                    context: TestOutputContext {
-                        time_taken: Some(runner_output.context.time_taken),
-                        output,
-                    },
-                },
-                Err(e) => EmuContext {
-                    candidate: runner_output.candidate.clone(),
-                    context: TestOutputContext {
-                        time_taken: Some(runner_output.context.time_taken),
-                        output: TestOutputType::Error(TestOutputError { reason: Arc::new(e) }),
-                    },
-                },
+        let frames = std::mem::take(&mut runner_output.context.frame_output);
+        let text = runner_output.context.text.take();
+        let to_test_output = |location: FrameLocation, output: anyhow::Result<TestOutputType>| EmuContext {
+            candidate: runner_output.candidate.clone(),
+            context: TestOutputContext {
+                time_taken: Some(runner_output.context.time_taken),
+                frame: Some(location),
+                metadata: runner_output.context.metadata.clone(),
+                cached: runner_output.context.cached,
+                peak_memory: runner_output.context.peak_memory,
+                output: output.unwrap_or_else(|e| {
+                    TestOutputType::Error(TestOutputError {
+                        kind: None,
+                        reason: Arc::new(e),
+                    })
+                }),
+            },
+        };
+        let sub_tests = self
+            .frame_and_path_definitions(&runner_output, frames)
+            .collect::<Vec<_>>();
+        let cache_entry = cache.map(|_| {
+            let cached_sub_tests = sub_tests
+                .iter()
+                .map(|(frames, path_def, encoding)| CachedSubTest {
+                    path: path_def.frame_location(None).new_path,
+                    animated: *encoding == FrameEncoding::AnimatedGif,
+                    tags: frames.iter().map(|frame| frame.tag.clone()).collect(),
+                })
+                .collect::<Vec<_>>();
+
+            (cached_sub_tests, text.clone())
+        });
+        // Frames are processed in parallel as well, as a single sequence test can easily produce hundreds of them.
+        let mut outputs: Vec<TestOutput> = sub_tests
+            .into_par_iter()
+            .map(|(frames, path_def, encoding)| {
+                let tag = match encoding {
+                    FrameEncoding::Image => frames.first().and_then(|frame| frame.tag.clone()),
+                    FrameEncoding::AnimatedGif => None,
+                };
+                let location = path_def.frame_location(tag);
+
+                to_test_output(
+                    location,
+                    self.process_frame(&runner_output.candidate, frames, encoding, path_def),
+                )
+            })
+            .collect();
+
+        if let Some(text) = text {
+            let path_def = self.text_path_definitions(&runner_output.candidate.rom_id);
+            let location = path_def.frame_location(None);
+
+            outputs.push(to_test_output(location, self.process_text(text, path_def)));
+        }
+
+        let errored = outputs
+            .iter()
+            .any(|output| matches!(output.context.output, TestOutputType::Error(_)));
+
+        if let (Some(cache), Some((sub_tests, text)), false) = (cache, cache_entry, errored) {
+            cache.insert(
+                &runner_output.candidate.rom_id,
+                sub_tests,
+                text,
+                runner_output.context.metadata.as_ref().clone(),
+            );
+        }
+
+        outputs
+    }
+
+    /// Compare the text output of a test against its text snapshot, see [EmulatorOutput::text].
+    ///
+    /// The outcomes mirror those of [EmuTestRunner::process_frame], with text files instead of frames.
+    fn process_text(&self, text: String, path_def: PathDefinitions) -> anyhow::Result<TestOutputType> {
+        let new_path = path_def.new_path()?;
+        // A dry run doesn't rotate the output directory, the text which would have become `old` is still in `new`.
+        let old_path =
+            if self.options.dry_run && self.options.rotate_output { new_path.clone() } else { path_def.old_path() };
+        let compare_old = !self.options.snapshot_only;
+        // Read the old text before writing the new one, as the two can be the same file during a dry run.
+        let git_baseline = match &self.options.baseline {
+            Baseline::GitRef { reference, path } if compare_old => {
+                Some(self.read_git_baseline(reference, &path_def.relative_path_in(path))?)
+            }
+            _ => None,
+        };
+        let old_text = match &git_baseline {
+            Some(data) => data.as_deref().map(|data| String::from_utf8_lossy(data).into_owned()),
+            None if !compare_old => None,
+            None => match std::fs::read_to_string(&old_path) {
+                Ok(old_text) => Some(old_text),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    self.warn_unreadable_old(&anyhow::Error::new(e).context(format!("Couldn't read {old_path:?}")));
+                    None
+                }
+            },
+        };
+
+        self.write_output(text.as_bytes(), &new_path)?;
+
+        let snapshots = path_def
+            .existing_snapshot_paths()?
+            .into_iter()
+            .map(|snapshot_path| {
+                let expected = std::fs::read_to_string(&snapshot_path)
+                    .with_context(|| format!("Couldn't read text snapshot {snapshot_path:?}"))?;
+
+                Ok((snapshot_path, expected))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let output = if snapshots.iter().any(|(_, expected)| *expected == text) {
+            TestOutputType::Passed(TestOutputPassed {
+                is_new: compare_old && old_text.as_ref() != Some(&text),
             })
-            .collect()
+        } else if !snapshots.is_empty() {
+            let snapshots_tried = snapshots.len();
+            let old_matches_snapshot = snapshots
+                .iter()
+                .any(|(_, expected)| old_text.as_ref() == Some(expected));
+            let (snapshot_path, expected) = snapshots.into_iter().next().unwrap();
+            let new_failure_path = path_def.failed_path_with_suffix("fail")?;
+            self.copy_output(&new_path, &new_failure_path)?;
+
+            if self.options.copy_comparison_image {
+                let expected_file_in_failure_path = path_def.failed_path_with_suffix("pass")?;
+                self.copy_output(&snapshot_path, &expected_file_in_failure_path)?;
+            }
+
+            TestOutputType::Failure(TestOutputFailure {
+                failure_path: new_failure_path,
+                snapshot_path,
+                snapshots_tried,
+                is_new: old_matches_snapshot,
+                old_path: (git_baseline.is_none() && old_text.is_some()).then(|| old_path.clone()),
+                kind: FailureKind::Text { expected, actual: text },
+                frames: None,
+            })
+        } else if !compare_old {
+            TestOutputType::NoSnapshot(TestOutputNoSnapshot {})
+        } else if old_text.as_ref() != Some(&text) {
+            let changed_path = path_def.changed_path_with_suffix("new")?;
+            self.copy_output(&new_path, &changed_path)?;
+
+            let old_path = match (&git_baseline, &old_text) {
+                // There is no `old` text on disk, so always provide a copy of the one in git instead.
+                (Some(_), Some(old_text)) => {
+                    let old_file_in_changed_path = path_def.changed_path_with_suffix("old")?;
+                    self.write_output(old_text.as_bytes(), &old_file_in_changed_path)?;
+                    old_file_in_changed_path
+                }
+                _ => {
+                    if self.options.copy_comparison_image && old_text.is_some() {
+                        let old_file_in_changed_path = path_def.changed_path_with_suffix("old")?;
+                        self.copy_output(&old_path, &old_file_in_changed_path)?;
+                    }
+
+                    old_path
+                }
+            };
+
+            TestOutputType::Changed(TestOutputChanged { changed_path, old_path })
+        } else {
+            TestOutputType::Unchanged(TestOutputUnchanged {
+                newly_added: old_text.is_none(),
+            })
+        };
+
+        Ok(output)
     }
 
-    fn process_frame(&self, frame: &FrameOutput, path_def: PathDefinitions) -> anyhow::Result<TestOutputType> {
+    fn process_frame(
+        &self,
+        candidate: &TestCandidate,
+        frames: Vec<FrameOutput>,
+        encoding: FrameEncoding,
+        path_def: PathDefinitions,
+    ) -> anyhow::Result<TestOutputType> {
         let new_path = path_def.new_path()?;
-        let old_path = path_def.old_path()?;
-        let snapshot_path = path_def.snapshot_path()?;
-        let image_frame = self.save_image(frame, &new_path)?;
-
-        let old_equals_data = |new_data: &[u8]| {
-            if old_path.exists() {
-                image::open(&old_path)
-                    .map(|data| data.as_bytes() == new_data)
-                    .unwrap_or(false)
+        // A dry run doesn't rotate the output directory, the frames which would have become `old` are still in `new`.
+        let old_path =
+            if self.options.dry_run && self.options.rotate_output { new_path.clone() } else { path_def.old_path() };
+        let retained_frames = self
+            .options
+            .retain_failure_frames
+            .then(|| frames.iter().map(|output| output.frame.clone()).collect::<Arc<[_]>>());
+        let (image_frames, encoded_frames) = self.prepare_frames(frames, encoding)?;
+        let new_hash = self
+            .options
+            .hash_sidecars
+            .then(|| processing::frames_hash(&image_frames));
+        // With `snapshot_only` the `old` frames are never looked at, the frames are only compared to their snapshots.
+        let compare_old = !self.options.snapshot_only;
+        // `None` if the baseline is the `old` directory, otherwise the contents of the frame in git (if it exists).
+        let git_baseline = match &self.options.baseline {
+            Baseline::GitRef { reference, path } if compare_old => {
+                Some(self.read_git_baseline(reference, &path_def.relative_path_in(path))?)
+            }
+            _ => None,
+        };
+        let old_exists = compare_old
+            && match &git_baseline {
+                Some(data) => data.is_some(),
+                None => old_path.exists(),
+            };
+        let old_hash = (compare_old && self.options.hash_sidecars && git_baseline.is_none())
+            .then(|| processing::read_hash_sidecar(&old_path))
+            .flatten();
+
+        let tolerance = candidate.tolerance.as_ref().or(self.options.tolerance.as_ref());
+        let region = self.options.compare_region.as_ref();
+        let masks = &self.options.ignore_masks;
+        // Whether the `old` frame matches the given data, within the `tolerance` if one is given.
+        let old_equals_data = |new_data: &[DynamicImage],
+                               new_data_hash: Option<&blake3::Hash>,
+                               tolerance: Option<&PixelTolerance>| {
+            let hashes = old_hash.as_ref().zip(new_data_hash);
+
+            if let Some((old_hash, new_data_hash)) =
+                hashes.filter(|(old, new)| old == new || (tolerance.is_none() && region.is_none() && masks.is_empty()))
+            {
+                // Differing hashes are only conclusive if the full frames have to be identical.
+                old_hash == new_data_hash
+            } else if old_exists {
+                let old_data = match &git_baseline {
+                    Some(data) => self.decode_frames(data.as_deref().unwrap_or_default().to_vec(), encoding, &old_path),
+                    None => self.load_frames(&old_path, encoding),
+                };
+
+                match old_data {
+                    Ok(data) => processing::sequences_match(
+                        &processing::comparison_view(&data, region, masks),
+                        &processing::comparison_view(new_data, region, masks),
+                        tolerance,
+                    ),
+                    Err(e) => {
+                        // Unlike a missing `old` frame, which is expected for new tests, this hints at a real problem.
+                        self.warn_unreadable_old(&e);
+                        false
+                    }
+                }
             } else {
                 false
             }
         };
 
-        let output = if snapshot_path.exists() {
+        let snapshot_paths = path_def.existing_snapshot_paths()?;
+        // Without a snapshot the frames are only checked for *any* change compared to the `old` frames.
+        let unchanged =
+            (snapshot_paths.is_empty() && compare_old).then(|| old_equals_data(&image_frames, new_hash.as_ref(), None));
+        // Unchanged frames are identical to the `old` frames on disk, which can then be linked instead of saved again.
+        // A `compare_region` or `ignore_masks` can hide differences, in which case the frames are always saved.
+        let link_old = !self.options.write_unchanged_new
+            && unchanged == Some(true)
+            && git_baseline.is_none()
+            && region.is_none()
+            && masks.is_empty();
+
+        if link_old {
+            self.link_output(&old_path, &new_path)?;
+        } else {
+            self.write_frames(&image_frames, encoded_frames.as_deref(), &new_path)?;
+        }
+
+        // Only written once the frame itself is, as a sidecar older than its frame is treated as stale.
+        if let (Some(new_hash), false) = (&new_hash, self.options.dry_run) {
+            processing::write_hash_sidecar(&new_path, new_hash)?;
+        }
+
+        // The outcome of the first snapshot which the frames match, and otherwise all snapshots they didn't match.
+        let mut matched = None;
+        let mut mismatches = Vec::new();
+
+        for snapshot_path in snapshot_paths {
+            let snapshot_hash = self
+                .options
+                .hash_sidecars
+                .then(|| processing::read_hash_sidecar(&snapshot_path))
+                .flatten();
+
+            if new_hash.is_some() && new_hash == snapshot_hash {
+                // The frame is identical to its snapshot, no need to decode it.
+                matched = Some(TestOutputPassed {
+                    is_new: compare_old && !old_equals_data(&image_frames, new_hash.as_ref(), tolerance),
+                });
+                break;
+            }
+
             // Time to see if our snapshot is still correct
-            let snapshot_data = image::open(&snapshot_path)?;
-            if snapshot_data.as_bytes() != image_frame.as_bytes() {
-                let new_failure_path = path_def.failed_path_with_suffix("fail")?;
-                std::fs::copy(&new_path, &new_failure_path)?;
-
-                if self.options.copy_comparison_image {
-                    let expected_file_in_failure_path = path_def.failed_path_with_suffix("pass")?;
-                    std::fs::copy(&snapshot_path, expected_file_in_failure_path)?;
+            let snapshot_data = self.load_frames(&snapshot_path, encoding)?;
+
+            match self.compare_to_snapshot(&snapshot_data, &image_frames, tolerance) {
+                None => {
+                    matched = Some(TestOutputPassed {
+                        is_new: compare_old && !old_equals_data(&snapshot_data, snapshot_hash.as_ref(), tolerance),
+                    });
+                    break;
                 }
+                Some(kind) => mismatches.push((snapshot_path, snapshot_data, snapshot_hash, kind)),
+            }
+        }
 
-                TestOutputType::Failure(TestOutputFailure {
-                    failure_path: new_failure_path,
-                    snapshot_path,
-                    is_new: old_equals_data(snapshot_data.as_bytes()),
-                })
-            } else {
-                TestOutputType::Passed(TestOutputPassed {
-                    is_new: !old_equals_data(snapshot_data.as_bytes()),
-                })
+        let output = if let Some(passed) = matched {
+            TestOutputType::Passed(passed)
+        } else if !mismatches.is_empty() {
+            let snapshots_tried = mismatches.len();
+            // A failure is only new if the `old` frames passed, by matching any of the snapshots.
+            let is_new = mismatches.iter().any(|(_, snapshot_data, snapshot_hash, _)| {
+                old_equals_data(snapshot_data, snapshot_hash.as_ref(), tolerance)
+            });
+            let (snapshot_path, _, _, kind) = mismatches.swap_remove(0);
+            let new_failure_path = path_def.failed_path_with_suffix("fail")?;
+            self.copy_output(&new_path, &new_failure_path)?;
+
+            if self.options.copy_comparison_image {
+                let expected_file_in_failure_path = path_def.failed_path_with_suffix("pass")?;
+                self.copy_output(&snapshot_path, &expected_file_in_failure_path)?;
             }
+
+            TestOutputType::Failure(TestOutputFailure {
+                failure_path: new_failure_path,
+                snapshot_path,
+                snapshots_tried,
+                is_new,
+                old_path: (old_exists && git_baseline.is_none()).then(|| old_path.clone()),
+                kind,
+                frames: retained_frames,
+            })
+        } else if !compare_old {
+            TestOutputType::NoSnapshot(TestOutputNoSnapshot {})
         } else {
             // Just check if there has been *any* change at all
-            if !old_equals_data(image_frame.as_bytes()) {
+            if unchanged != Some(true) {
                 let changed_path = path_def.changed_path_with_suffix("new")?;
-                std::fs::copy(&new_path, &changed_path)?;
+                self.copy_output(&new_path, &changed_path)?;
 
-                if self.options.copy_comparison_image && old_path.exists() {
-                    let old_file_in_changed_path = path_def.changed_path_with_suffix("old")?;
-                    std::fs::copy(&old_path, old_file_in_changed_path)?;
-                }
+                let old_path = match &git_baseline {
+                    // There is no `old` frame on disk, so always provide a copy of the one in git instead.
+                    Some(Some(data)) => {
+                        let old_file_in_changed_path = path_def.changed_path_with_suffix("old")?;
+                        self.write_output(data, &old_file_in_changed_path)?;
+                        old_file_in_changed_path
+                    }
+                    _ => {
+                        if self.options.copy_comparison_image && old_exists {
+                            let old_file_in_changed_path = path_def.changed_path_with_suffix("old")?;
+                            self.copy_output(&old_path, &old_file_in_changed_path)?;
+                        }
+
+                        old_path
+                    }
+                };
 
                 TestOutputType::Changed(TestOutputChanged { changed_path, old_path })
             } else {
                 TestOutputType::Unchanged(TestOutputUnchanged {
-                    newly_added: !old_path.exists(),
+                    newly_added: !old_exists,
                 })
             }
         };
@@ -240,53 +963,798 @@ impl EmuTestRunner {
         Ok(output)
     }
 
-    fn save_image<'a>(
-        &'a self,
-        frame: &'a FrameOutput,
-        path_to_save: &Path,
-    ) -> anyhow::Result<ImageBuffer<Rgba<u8>, &'a [u8]>> {
-        let image_frame = ImageBuffer::from_raw(
-            self.options.expected_frame_width as u32,
-            self.options.expected_frame_height as u32,
-            frame.frame.0.as_slice(),
-        )
-        .context("Failed to turn framebuffer into a dynamic image")?;
+    /// Compare the frames of a test to one of its snapshots, with the comparator if one was set.
+    ///
+    /// Only the parts of the frames within the `compare_region` and outside of the `ignore_masks` are compared.
+    ///
+    /// # Returns
+    /// Why the frames don't match the snapshot, `None` if they do.
+    fn compare_to_snapshot(
+        &self,
+        snapshot_data: &[DynamicImage],
+        image_frames: &[DynamicImage],
+        tolerance: Option<&PixelTolerance>,
+    ) -> Option<FailureKind> {
+        let region = self.options.compare_region.as_ref();
+        let masks = &self.options.ignore_masks;
+        let snapshot_view = processing::comparison_view(snapshot_data, region, masks);
+        let frame_view = processing::comparison_view(image_frames, region, masks);
+
+        if let Some(comparator) = &self.comparator {
+            if snapshot_view.len() != frame_view.len() {
+                return Some(FailureKind::Custom {
+                    reason: format!(
+                        "snapshot has {} frames but the test produced {}",
+                        snapshot_view.len(),
+                        frame_view.len()
+                    ),
+                });
+            }
+
+            return snapshot_view
+                .iter()
+                .zip(frame_view.iter())
+                .find_map(|(snapshot, frame)| match comparator(snapshot, frame) {
+                    Comparison::Match => None,
+                    Comparison::Mismatch { reason } => Some(FailureKind::Custom { reason }),
+                });
+        }
+
+        let diff = processing::diff_sequences(&snapshot_view, &frame_view, tolerance).unwrap_or_else(|| {
+            let total_pixels = image_frames
+                .iter()
+                .map(|image| image.width() as usize * image.height() as usize)
+                .sum();
+
+            FrameDiff {
+                differing_pixels: total_pixels,
+                total_pixels,
+                max_channel_delta: u8::MAX,
+            }
+        });
+
+        if diff.is_within(tolerance) {
+            return None;
+        }
+
+        let dimension_mismatch = snapshot_data
+            .iter()
+            .zip(image_frames)
+            .find(|(snapshot, frame)| (snapshot.width(), snapshot.height()) != (frame.width(), frame.height()));
+
+        Some(match dimension_mismatch {
+            Some((snapshot, frame)) => FailureKind::Dimensions {
+                snapshot_width: snapshot.width(),
+                snapshot_height: snapshot.height(),
+                frame_width: frame.width(),
+                frame_height: frame.height(),
+            },
+            None => FailureKind::Image {
+                differing_pixels: diff.differing_pixels,
+                max_channel_delta: diff.max_channel_delta,
+            },
+        })
+    }
+
+    /// Convert the `frames` to images in the given `encoding`, without saving them yet.
+    ///
+    /// # Returns
+    /// The frames as they would be read back from disk, which for animated GIFs can differ from the original frames.
+    /// For animated GIFs the encoded file is returned as well, to be passed to [EmuTestRunner::write_frames].
+    fn prepare_frames(
+        &self,
+        frames: Vec<FrameOutput>,
+        encoding: FrameEncoding,
+    ) -> anyhow::Result<(Vec<DynamicImage>, Option<Vec<u8>>)> {
+        let images = frames
+            .into_iter()
+            .map(|frame| self.frame_to_image(frame))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        match encoding {
+            FrameEncoding::Image => Ok((images, None)),
+            FrameEncoding::AnimatedGif => {
+                let data = processing::encode_gif(&images)?;
+
+                Ok((processing::decode_gif(&data)?, Some(data)))
+            }
+        }
+    }
+
+    /// Save the frames returned by [EmuTestRunner::prepare_frames].
+    fn write_frames(&self, images: &[DynamicImage], encoded: Option<&[u8]>, path_to_save: &Path) -> anyhow::Result<()> {
+        match encoded {
+            Some(data) => self.write_output(data, path_to_save),
+            None => images.iter().try_for_each(|image| self.save_image(image, path_to_save)),
+        }
+    }
+
+    /// Reproduce the output of the emulator from the frames saved by a previous run, see [RunCache].
+    ///
+    /// # Returns
+    /// `None` if any of the frames can no longer be read, or don't match the current options.
+    fn load_cached_output(&self, entry: &cache::CacheEntry) -> Option<EmulatorOutput> {
+        let mut frames = Vec::new();
+
+        for sub_test in &entry.sub_tests {
+            let encoding = if sub_test.animated { FrameEncoding::AnimatedGif } else { FrameEncoding::Image };
+            let images = self.load_frames(&sub_test.path, encoding).ok()?;
+
+            if images.len() != sub_test.tags.len() {
+                return None;
+            }
+
+            for (image, tag) in images.into_iter().zip(&sub_test.tags) {
+                frames.push(FrameOutput {
+                    tag: tag.clone(),
+                    frame: self.image_to_frame(image)?,
+                });
+            }
+        }
 
-        image_frame.save(path_to_save)?;
+        Some(EmulatorOutput {
+            frames,
+            metadata: entry.metadata.clone(),
+            text: entry.text.clone(),
+        })
+    }
+
+    /// The inverse of [EmuTestRunner::frame_to_image], `None` if the `image` can't be represented in the configured
+    /// [PixelFormat].
+    fn image_to_frame(&self, image: DynamicImage) -> Option<RgbaFrame> {
+        let dimensions = (self.options.expected_frame_width, self.options.expected_frame_height);
+        if (image.width() as usize, image.height() as usize) != dimensions {
+            return None;
+        }
+
+        let data = match &self.options.frame_pixel_format {
+            PixelFormat::Rgba8 => image.into_rgba8().into_raw(),
+            PixelFormat::Rgb8 => image.into_rgb8().into_raw(),
+            PixelFormat::Luma8 => image.into_luma8().into_raw(),
+            PixelFormat::Indexed { palette } => image
+                .into_rgba8()
+                .pixels()
+                .map(|pixel| {
+                    let index = palette.iter().position(|colour| *colour == pixel.0)?;
+                    u8::try_from(index).ok()
+                })
+                .collect::<Option<Vec<_>>>()?,
+        };
+
+        Some(RgbaFrame(data))
+    }
+
+    fn frame_to_image(&self, frame: FrameOutput) -> anyhow::Result<DynamicImage> {
+        let width = self.options.expected_frame_width as u32;
+        let height = self.options.expected_frame_height as u32;
+        let format = &self.options.frame_pixel_format;
+        let expected_len =
+            self.options.expected_frame_width * self.options.expected_frame_height * format.bytes_per_pixel();
+        let actual_len = frame.frame.0.len();
+
+        if actual_len != expected_len {
+            anyhow::bail!(
+                "Framebuffer has a length of {actual_len} bytes, but a {width}x{height} {format:?} frame requires {expected_len} bytes"
+            );
+        }
+
+        let image_frame = match format {
+            PixelFormat::Rgba8 => ImageBuffer::from_raw(width, height, frame.frame.0).map(DynamicImage::ImageRgba8),
+            PixelFormat::Rgb8 => ImageBuffer::from_raw(width, height, frame.frame.0).map(DynamicImage::ImageRgb8),
+            PixelFormat::Luma8 => ImageBuffer::from_raw(width, height, frame.frame.0).map(DynamicImage::ImageLuma8),
+            PixelFormat::Indexed { palette } => {
+                let mut rgba = Vec::with_capacity(frame.frame.0.len() * 4);
+
+                for &index in &frame.frame.0 {
+                    let colour = palette.get(index as usize).with_context(|| {
+                        format!(
+                            "Palette index {index} is out of range for a palette of {} colours",
+                            palette.len()
+                        )
+                    })?;
+                    rgba.extend_from_slice(colour);
+                }
+
+                ImageBuffer::from_raw(width, height, rgba).map(DynamicImage::ImageRgba8)
+            }
+        }
+        .with_context(|| {
+            format!("Failed to turn framebuffer into a dynamic image (expected = {expected_len} bytes, actual = {actual_len} bytes)")
+        })?;
 
         Ok(image_frame)
     }
 
-    /// Return an iterator which contains a tuple of a [FrameOutput] and the [PathDefinitions] where this frame may be saved
-    /// after further classification.
+    /// Save a single frame in the configured [OutputImageFormat](options::OutputImageFormat), unless this is a dry run.
+    fn save_image(&self, image_frame: &DynamicImage, path_to_save: &Path) -> anyhow::Result<()> {
+        if self.options.dry_run {
+            return Ok(());
+        }
+
+        match self.options.output_image_format {
+            OutputImageFormat::Png => std::fs::File::create(path_to_save)
+                .map_err(image::ImageError::IoError)
+                .and_then(|file| {
+                    image_frame.write_with_encoder(PngEncoder::new_with_quality(
+                        std::io::BufWriter::new(file),
+                        self.options.png_compression.png_compression(),
+                        FilterType::default(),
+                    ))
+                }),
+            format => match format.image_format() {
+                Some(image_format) => image_frame.save_with_format(path_to_save, image_format),
+                None => std::fs::write(path_to_save, image_frame.as_bytes()).map_err(image::ImageError::IoError),
+            },
+        }
+        .with_context(|| format!("Couldn't save frame to {path_to_save:?}"))
+    }
+
+    /// Report an `old` frame or text which exists, but couldn't be read.
+    ///
+    /// The output is then treated as changed compared to the `old` one, which would otherwise silently skew the
+    /// `newly_added`, `is_new`, and `changed` results.
+    fn warn_unreadable_old(&self, error: &anyhow::Error) {
+        let _ = self.formatter.handle_warning(&format!(
+            "Couldn't read the output of the previous run, treating it as changed: {error:#}"
+        ));
+    }
+
+    /// Write a file into the output directory, unless this is a dry run.
+    fn write_output(&self, data: &[u8], to: &Path) -> anyhow::Result<()> {
+        if !self.options.dry_run {
+            std::fs::write(to, data).with_context(|| format!("Couldn't write {to:?}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy a file into the output directory, unless this is a dry run.
+    fn copy_output(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        if !self.options.dry_run {
+            std::fs::copy(from, to).with_context(|| format!("Couldn't copy {from:?} to {to:?}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Hard link a file into the output directory, unless this is a dry run.
+    ///
+    /// Falls back to copying the file if it can't be linked, for example because the file system doesn't support it.
+    fn link_output(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        if !self.options.dry_run && std::fs::hard_link(from, to).is_err() {
+            std::fs::copy(from, to).with_context(|| format!("Couldn't copy {from:?} to {to:?}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Load frames which were previously saved in the given `encoding`.
+    fn load_frames(&self, path: &Path, encoding: FrameEncoding) -> anyhow::Result<Vec<DynamicImage>> {
+        let data = std::fs::read(path).with_context(|| format!("Couldn't read frame {path:?}"))?;
+
+        self.decode_frames(data, encoding, path)
+    }
+
+    /// Decode the contents of a file containing frames in the given `encoding`.
+    ///
+    /// The `path` is only used to describe errors.
+    fn decode_frames(&self, data: Vec<u8>, encoding: FrameEncoding, path: &Path) -> anyhow::Result<Vec<DynamicImage>> {
+        match encoding {
+            FrameEncoding::Image => self.decode_image(data, path).map(|image| vec![image]),
+            FrameEncoding::AnimatedGif => {
+                processing::decode_gif(&data).with_context(|| format!("Couldn't decode GIF frames {path:?}"))
+            }
+        }
+    }
+
+    /// Decode the contents of a frame file in the configured [OutputImageFormat](options::OutputImageFormat).
+    ///
+    /// The `path` is only used to describe errors.
+    fn decode_image(&self, data: Vec<u8>, path: &Path) -> anyhow::Result<DynamicImage> {
+        let Some(image_format) = self.options.output_image_format.image_format() else {
+            let width = self.options.expected_frame_width as u32;
+            let height = self.options.expected_frame_height as u32;
+
+            let image = match &self.options.frame_pixel_format {
+                PixelFormat::Rgba8 | PixelFormat::Indexed { .. } => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+                }
+                PixelFormat::Rgb8 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+                PixelFormat::Luma8 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+            };
+
+            return image.with_context(|| format!("Raw frame {path:?} doesn't match the expected frame dimensions"));
+        };
+
+        image::load_from_memory_with_format(&data, image_format)
+            .with_context(|| format!("Couldn't decode {image_format:?} frame {path:?}"))
+    }
+
+    /// Read a baseline frame from git, caching the result for subsequent runs.
+    ///
+    /// # Returns
+    /// `None` if the frame doesn't exist in the given `reference`. Any other failure, such as a `reference` which
+    /// doesn't exist or not being in a git repository at all, is an error.
+    fn read_git_baseline(&self, reference: &str, path: &Path) -> anyhow::Result<Option<Arc<[u8]>>> {
+        // Git always expects forward slashes, regardless of platform.
+        let object = format!("{reference}:{}", path.to_string_lossy().replace('\\', "/"));
+
+        if let Some(cached) = self.git_baseline_cache.lock().unwrap().get(&object) {
+            return Ok(cached.clone());
+        }
+
+        let output = std::process::Command::new("git")
+            .args(["show", &object])
+            .output()
+            .context("Couldn't run `git show` to read the baseline")?;
+        let data = if output.status.success() {
+            Some(Arc::from(output.stdout))
+        } else {
+            let resolves = |name: &str| -> anyhow::Result<bool> {
+                let status = std::process::Command::new("git")
+                    .args(["rev-parse", "--verify", "--quiet", name])
+                    .stdout(std::process::Stdio::null())
+                    .status()
+                    .context("Couldn't run `git rev-parse` to verify the baseline")?;
+
+                Ok(status.success())
+            };
+            let error = String::from_utf8_lossy(&output.stderr);
+
+            // Only a frame which is missing from an existing commit is a frame without a baseline.
+            if !resolves(&format!("{reference}^{{commit}}"))? {
+                anyhow::bail!(
+                    "Git reference `{reference}` of the baseline couldn't be resolved: {}",
+                    error.trim()
+                );
+            }
+            if resolves(&object)? {
+                anyhow::bail!("Couldn't read baseline frame `{object}` from git: {}", error.trim());
+            }
+
+            None
+        };
+
+        self.git_baseline_cache.lock().unwrap().insert(object, data.clone());
+
+        Ok(data)
+    }
+
+    /// Return an iterator which contains a tuple of the [FrameOutput]s of a single sub-test, the [PathDefinitions] where
+    /// these frames may be saved after further classification, and how they're saved.
     fn frame_and_path_definitions<'a>(
         &'a self,
         runner_output: &'a RunnerOutput,
-    ) -> impl Iterator<Item = (&'a FrameOutput, PathDefinitions<'a>)> {
-        let is_sequence_test = runner_output.context.frame_output.len() > 1;
-        let create_subfolder = is_sequence_test && self.options.put_sequence_tests_in_subfolder;
+        frames: Vec<FrameOutput>,
+    ) -> impl Iterator<Item = (Vec<FrameOutput>, PathDefinitions<'a>, FrameEncoding)> {
+        let is_sequence_test = frames.len() > 1 || runner_output.candidate.is_sequence_test;
         let rom_id = &runner_output.candidate.rom_id;
+        let extension = self.options.output_image_format.extension();
 
-        runner_output.context.frame_output.iter().map(move |frame| {
-            let frame_file_png = setup::rom_id_to_png(rom_id, frame.tag.as_deref());
+        let (sub_tests, encoding) = if is_sequence_test && self.options.sequence_output == SequenceMode::AnimatedGif {
+            let sub_tests = if frames.is_empty() { Vec::new() } else { vec![frames] };
+            (sub_tests, FrameEncoding::AnimatedGif)
+        } else {
+            (
+                frames.into_iter().map(|frame| vec![frame]).collect(),
+                FrameEncoding::Image,
+            )
+        };
+        // An animated GIF already contains all frames of the sequence in a single file.
+        let create_subfolder =
+            is_sequence_test && self.options.put_sequence_tests_in_subfolder && encoding == FrameEncoding::Image;
+
+        sub_tests.into_iter().enumerate().map(move |(index, frames)| {
+            let frame_file_name = match encoding {
+                // Untagged frames of a sequence test are told apart by their index, so they don't overwrite each other.
+                FrameEncoding::Image if is_sequence_test && frames[0].tag.is_none() => {
+                    setup::rom_id_to_file_name(rom_id, Some(&index.to_string()), extension)
+                }
+                FrameEncoding::Image => setup::rom_id_to_file_name(rom_id, frames[0].tag.as_deref(), extension),
+                FrameEncoding::AnimatedGif => setup::rom_id_to_file_name(rom_id, None, "gif"),
+            };
 
             (
-                frame,
+                frames,
                 PathDefinitions::new(
                     &self.options.output_path,
+                    &self.options.output_layout,
                     &self.options.snapshot_path,
-                    create_subfolder.then(|| Path::new(rom_id)),
-                    frame_file_png,
+                    self.options.snapshot_variant.as_deref(),
+                    create_subfolder.then(|| PathBuf::from(setup::sanitize_file_name(rom_id).as_ref())),
+                    frame_file_name,
+                    !self.options.dry_run,
                 ),
+                encoding,
             )
         })
     }
+
+    /// The [PathDefinitions] for the text output of a test, stored as `{rom_id}.txt` next to its frames.
+    fn text_path_definitions<'a>(&'a self, rom_id: &str) -> PathDefinitions<'a> {
+        PathDefinitions::new(
+            &self.options.output_path,
+            &self.options.output_layout,
+            &self.options.snapshot_path,
+            self.options.snapshot_variant.as_deref(),
+            None,
+            setup::rom_id_to_file_name(rom_id, None, "txt"),
+            !self.options.dry_run,
+        )
+    }
 }
 
-fn start_timeout_killer(wait: Duration) {
-    std::thread::spawn(move || {
-        std::thread::sleep(wait);
-        println!("Timeout reached, killing process");
-        std::process::exit(1)
-    });
+/// How the frames of a single sub-test are stored on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameEncoding {
+    /// A single frame, stored in the configured [OutputImageFormat](options::OutputImageFormat).
+    Image,
+    /// All frames of a sequence test, stored as a single animated GIF.
+    AnimatedGif,
+}
+
+/// The point in time at which a test has to be completed, and the error to report if it isn't.
+#[derive(Debug, Clone, Copy)]
+struct Deadline {
+    at: Instant,
+    error: TimeoutError,
+}
+
+impl Deadline {
+    fn earliest(left: Option<Deadline>, right: Option<Deadline>) -> Option<Deadline> {
+        match (left, right) {
+            (Some(left), Some(right)) => Some(if left.at <= right.at { left } else { right }),
+            (left, right) => left.or(right),
+        }
+    }
+}
+
+/// Run `op` in the given `pool`, or in the current pool (usually rayon's global pool) if there is none.
+fn install<R: Send>(pool: Option<&rayon::ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// Run the emulator on the current thread, converting any panic into an error.
+fn run_emulator<F, R>(candidate: &TestCandidate, rom_data: Vec<u8>, emu_run: &F) -> anyhow::Result<EmulatorOutput>
+where
+    F: Fn(&TestCandidate, Vec<u8>) -> R + std::panic::RefUnwindSafe,
+    R: Into<EmulatorOutput>,
+{
+    let mark = panics::panic_mark();
+    let frame = std::panic::catch_unwind(|| emu_run(candidate, rom_data).into());
+    // Always take the panics, as the emulator may have caught some of its own which we should discard.
+    let panic = panics::take_panic_since(mark);
+
+    match frame {
+        Ok(frame) => Ok(frame),
+        Err(_) => Err(match panic {
+            Some(panic) => EmulatorPanic {
+                message: panic.panic_msg,
+                location: panic.location,
+                backtrace: panic.backtrace,
+            },
+            None => EmulatorPanic {
+                message: "Unknown panic".to_string(),
+                location: None,
+                backtrace: Backtrace::disabled(),
+            },
+        }
+        .into()),
+    }
+}
+
+/// Run the emulator on a dedicated watcher thread, giving up once the `deadline` has passed.
+///
+/// Rust offers no way to forcefully stop a thread, so a test which doesn't complete in time is left to run in the
+/// background. Its eventual result is discarded, and the thread is cleaned up once (or if) the emulator returns.
+fn run_emulator_watched<F, R>(
+    candidate: &Arc<TestCandidate>,
+    rom_data: Vec<u8>,
+    emu_run: &Arc<F>,
+    deadline: Deadline,
+) -> anyhow::Result<EmulatorOutput>
+where
+    F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+    R: Into<EmulatorOutput>,
+{
+    let remaining = deadline.at.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(deadline.error.into());
+    }
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let emu_run = emu_run.clone();
+    let thread_candidate = candidate.clone();
+
+    std::thread::Builder::new()
+        .name(format!("emu_test_{}", candidate.rom_id))
+        .spawn(move || {
+            let _ = sender.send(run_emulator(&thread_candidate, rom_data, emu_run.as_ref()));
+        })
+        .context("Couldn't spawn emulator thread")?;
+
+    match receiver.recv_timeout(remaining) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(deadline.error.into()),
+        Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Emulator thread exited without producing a result"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatters::collecting::CollectingFormatter;
+    use std::num::NonZeroUsize;
+    use std::time::Duration;
+
+    fn test_runner(options: EmuRunnerOptions) -> EmuTestRunner {
+        EmuTestRunner::new(Box::new(CollectingFormatter::new()), options).unwrap()
+    }
+
+    fn runner_output(candidate: TestCandidate, frames: Vec<FrameOutput>) -> RunnerOutput {
+        RunnerOutput {
+            candidate: Arc::new(candidate),
+            context: RunnerOutputContext {
+                time_taken: Duration::ZERO,
+                frame_output: frames,
+                metadata: Default::default(),
+                text: None,
+                attempts: 1,
+                cached: false,
+                peak_memory: None,
+            },
+        }
+    }
+
+    #[test]
+    fn untagged_sequence_frames_get_distinct_paths() {
+        let runner = test_runner(EmuRunnerOptions {
+            dry_run: true,
+            ..Default::default()
+        });
+        let frames = vec![
+            FrameOutput {
+                tag: None,
+                frame: RgbaFrame(Vec::new()),
+            };
+            3
+        ];
+        let output = runner_output(TestCandidate::new("sequence", "sequence.gb"), frames.clone());
+
+        let paths = runner
+            .frame_and_path_definitions(&output, frames)
+            .map(|(_, paths, _)| paths.frame_location(None).new_path)
+            .collect::<Vec<_>>();
+
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths.iter().collect::<std::collections::HashSet<_>>().len(), 3);
+    }
+
+    #[test]
+    fn empty_run_keeps_the_previous_output() {
+        let output_path = std::env::temp_dir().join(format!("emu_test_runner_empty_run_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&output_path);
+        let options = EmuRunnerOptions {
+            output_path: output_path.clone(),
+            ..Default::default()
+        };
+        let previous_frame = options.output_layout.new_path(&output_path).join("previous.png");
+        let old_dir = options.output_layout.old_path(&output_path);
+        std::fs::create_dir_all(previous_frame.parent().unwrap()).unwrap();
+        std::fs::write(&previous_frame, b"previous run").unwrap();
+
+        let report = test_runner(options)
+            .run_tests_streaming(
+                Vec::<TestCandidate>::new().into_iter(),
+                |_, _| Vec::<FrameOutput>::new(),
+                |_| {},
+            )
+            .unwrap();
+        let (kept_previous, created_old) = (previous_frame.exists(), old_dir.exists());
+        std::fs::remove_dir_all(&output_path).unwrap();
+
+        assert!(report.test_outputs.is_empty());
+        assert_eq!(report.original_tests_count, 0);
+        assert!(kept_previous, "the `new` directory should not have been rotated away");
+        assert!(!created_old, "the `old` directory should not have been created");
+    }
+
+    #[test]
+    fn snapshot_with_a_matching_sidecar_is_not_decoded() {
+        let _lock = panics::hook_lock();
+        let dir = std::env::temp_dir().join(format!("emu_test_runner_sidecar_match_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("hashed.gb");
+        std::fs::write(&rom_path, b"rom").unwrap();
+
+        let formatter = CollectingFormatter::new();
+        let options = EmuRunnerOptions {
+            output_path: dir.join("output"),
+            snapshot_path: dir.join("snapshots"),
+            expected_frame_width: 2,
+            expected_frame_height: 2,
+            hash_sidecars: true,
+            ..Default::default()
+        };
+        let runner = EmuTestRunner::new(Box::new(formatter.clone()), options).unwrap();
+        let frame = FrameOutput {
+            tag: None,
+            frame: RgbaFrame(vec![0x7F; 2 * 2 * 4]),
+        };
+
+        // The snapshot can't be decoded, so the test only passes if the sidecar's hash is trusted.
+        let output = runner_output(TestCandidate::new("hashed", &rom_path), vec![frame.clone()]);
+        let (_, paths, _) = runner
+            .frame_and_path_definitions(&output, vec![frame.clone()])
+            .next()
+            .unwrap();
+        let snapshot_path = paths.frame_location(None).snapshot_path;
+        let hash = processing::frames_hash(&[runner.frame_to_image(frame.clone()).unwrap()]);
+        std::fs::create_dir_all(snapshot_path.parent().unwrap()).unwrap();
+        std::fs::write(&snapshot_path, b"not an image").unwrap();
+        processing::write_hash_sidecar(&snapshot_path, &hash).unwrap();
+
+        let report = runner
+            .run_tests_with_report(std::iter::once(TestCandidate::new("hashed", &rom_path)), move |_, _| {
+                vec![frame.clone()]
+            })
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.passed.len(), 1, "{report:?}");
+    }
+
+    #[test]
+    fn old_frame_with_a_sidecar_from_the_previous_run_is_not_decoded() {
+        let _lock = panics::hook_lock();
+        let dir = std::env::temp_dir().join(format!("emu_test_runner_sidecar_old_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("hashed.gb");
+        std::fs::write(&rom_path, b"rom").unwrap();
+
+        let options = EmuRunnerOptions {
+            output_path: dir.join("output"),
+            snapshot_path: dir.join("snapshots"),
+            expected_frame_width: 2,
+            expected_frame_height: 2,
+            hash_sidecars: true,
+            ..Default::default()
+        };
+        let runner = test_runner(options.clone());
+        let run = || {
+            runner
+                .run_tests_with_report(std::iter::once(TestCandidate::new("hashed", &rom_path)), |_, _| {
+                    vec![FrameOutput {
+                        tag: None,
+                        frame: RgbaFrame(vec![0x7F; 2 * 2 * 4]),
+                    }]
+                })
+                .unwrap()
+        };
+
+        run();
+        // Make the frame undecodable without touching its modification time, so the second run can only consider it
+        // unchanged if the sidecar written by the first run is trusted.
+        let frame_path = options.output_layout.new_path(&options.output_path).join("hashed.png");
+        let frame_modified = std::fs::metadata(&frame_path).unwrap().modified().unwrap();
+        std::fs::write(&frame_path, b"not an image").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&frame_path)
+            .unwrap()
+            .set_modified(frame_modified)
+            .unwrap();
+
+        let report = run();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.unchanged.len(), 1, "{report:?}");
+    }
+
+    #[test]
+    fn filtered_runs_keep_the_output_of_other_tests() {
+        let _lock = panics::hook_lock();
+        let dir = std::env::temp_dir().join(format!("emu_test_runner_filtered_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let candidates = ["a", "b"].map(|rom_id| {
+            let rom_path = dir.join(format!("{rom_id}.gb"));
+            std::fs::write(&rom_path, rom_id).unwrap();
+            TestCandidate::new(rom_id, rom_path)
+        });
+
+        let options = EmuRunnerOptions {
+            output_path: dir.join("output"),
+            snapshot_path: dir.join("snapshots"),
+            expected_frame_width: 2,
+            expected_frame_height: 2,
+            emulator_version: Some("1".to_string()),
+            ..Default::default()
+        };
+        let runner = test_runner(options.clone());
+        let runs_of_b = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let emu_run = {
+            let runs_of_b = runs_of_b.clone();
+            move |candidate: &TestCandidate, _: Vec<u8>| {
+                if candidate.rom_id == "b" {
+                    runs_of_b.fetch_add(1, Ordering::SeqCst);
+                }
+
+                vec![FrameOutput {
+                    tag: None,
+                    frame: RgbaFrame(vec![0x7F; 2 * 2 * 4]),
+                }]
+            }
+        };
+
+        runner
+            .run_tests_with_report(candidates.clone().into_iter(), emu_run.clone())
+            .unwrap();
+        // Twice, as a single filtered run would still find `b` in the `old` directory.
+        for _ in 0..2 {
+            runner
+                .run_tests_filtered(candidates.clone(), |candidate| candidate.rom_id == "a", emu_run.clone())
+                .unwrap();
+        }
+        let kept_frame = options
+            .output_layout
+            .new_path(&options.output_path)
+            .join("b.png")
+            .exists();
+        let report = runner
+            .run_tests_with_report(candidates.clone().into_iter(), emu_run)
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(kept_frame);
+        assert_eq!(
+            runs_of_b.load(Ordering::SeqCst),
+            1,
+            "`b` should have been reused from the run cache"
+        );
+        assert_eq!(report.unchanged.len(), 2, "{report:?}");
+    }
+
+    #[test]
+    fn git_baseline_only_treats_missing_frames_as_absent() {
+        let in_repository = std::process::Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", "HEAD"])
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if !in_repository {
+            return;
+        }
+        let runner = test_runner(EmuRunnerOptions::default());
+
+        assert!(runner
+            .read_git_baseline("HEAD", Path::new("Cargo.toml"))
+            .unwrap()
+            .is_some());
+        assert!(runner
+            .read_git_baseline("HEAD", Path::new("missing/frame.png"))
+            .unwrap()
+            .is_none());
+        assert!(runner
+            .read_git_baseline("no-such-ref", Path::new("Cargo.toml"))
+            .is_err());
+    }
+
+    #[test]
+    fn memory_is_only_measured_with_a_single_test_thread() {
+        let runner_with_threads = |threads| {
+            test_runner(EmuRunnerOptions {
+                num_threads: NonZeroUsize::new(threads).unwrap(),
+                measure_memory: true,
+                ..Default::default()
+            })
+        };
+
+        assert!(runner_with_threads(1).measures_memory());
+        assert!(!runner_with_threads(2).measures_memory());
+    }
 }
@@ -0,0 +1,31 @@
+/// Reset the peak memory usage of the process to its current usage, does nothing on unsupported platforms.
+#[cfg(target_os = "linux")]
+pub fn reset_peak_memory() {
+    // `5` resets the peak resident set size, supported since Linux 4.0. Older kernels simply keep the peak of the
+    // entire process.
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+/// The peak memory usage of the process in bytes since the last [reset_peak_memory], if it can be measured.
+#[cfg(target_os = "linux")]
+pub fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reset_peak_memory() {}
+
+#[cfg(not(target_os = "linux"))]
+pub fn peak_memory() -> Option<u64> {
+    None
+}
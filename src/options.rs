@@ -1,33 +1,900 @@
+use std::fmt::{Debug, Display, Formatter};
 use std::num::NonZeroUsize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::Context;
+
+use crate::outputs::RgbaFrame;
+use crate::setup::{CHANGED_DIR_NAME, FAILED_DIR_NAME, NEW_DIR_NAME, OLD_DIR_NAME};
+
+/// The options of an [EmuTestRunner](crate::EmuTestRunner).
+///
+/// Can be stored in a config file through [EmuRunnerOptions::write_config_file], in which case any missing fields take
+/// their [Default] value.
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct EmuRunnerOptions {
     pub output_path: PathBuf,
+    /// The names of the directories created within the `output_path`.
+    pub output_layout: OutputLayout,
+    /// Where the frames of a previous run are read from, to determine whether a test `changed`.
+    pub baseline: Baseline,
+    /// Whether the frames of the previous run should replace the `old` directory at the start of a run.
+    ///
+    /// Disable this to keep a known-good `old` directory as the baseline for detecting changes, regardless of how
+    /// (or if) previous runs completed.
+    pub rotate_output: bool,
+    /// The directory containing the expected frames of the tests, which they fail if they don't match.
+    ///
+    /// A test with several valid outputs can have a directory in place of its snapshot, such as `{rom_id}.png/`. The
+    /// test then passes if it matches any of the files in that directory.
     pub snapshot_path: PathBuf,
+    /// The amount of threads used to run the emulator.
+    ///
+    /// Defaults to the available parallelism of the system, or a single thread if that can't be determined.
     pub num_threads: NonZeroUsize,
+    /// Run the tests on rayon's global thread pool, instead of a dedicated pool of `num_threads` threads.
+    ///
+    /// Useful when embedding the runner in an application which already configures the global pool, for example through
+    /// [rayon::ThreadPoolBuilder::build_global], to avoid creating a second set of threads. The `num_threads` are then
+    /// ignored, as the tests run on [rayon::current_num_threads] threads instead. A dedicated pool is still created for
+    /// processing the frames if `processing_threads` is set.
+    pub use_global_pool: bool,
+    /// The amount of threads used to save and compare the produced frames, defaults to `num_threads` when `None`.
+    ///
+    /// The frames are only processed once all tests have been ran, so the two pools are never busy at the same time.
+    /// Setting this allows, for example, a small pool for IO-heavy emulators while still saving frames in parallel.
+    ///
+    /// This also bounds how many frames are encoded, decoded, and compared at the same time, as at most one frame is in
+    /// flight per thread. Lower it to cap the memory usage of this stage on machines with many cores.
+    pub processing_threads: Option<NonZeroUsize>,
     pub expected_frame_width: usize,
     pub expected_frame_height: usize,
+    /// The format of the framebuffers returned by the emulator.
+    pub frame_pixel_format: PixelFormat,
+    /// Applied to every framebuffer returned by the emulator before it is saved or compared.
+    ///
+    /// The `expected_frame_width`, `expected_frame_height`, and `frame_pixel_format` describe the frame *after*
+    /// preprocessing. Can't be (de)serialized, and is thus always `None` when loaded from a file.
+    #[serde(skip)]
+    pub frame_preprocessor: Option<FramePreprocessor>,
+    /// The file format in which frames are saved, and in which snapshots are expected to be stored.
+    pub output_image_format: OutputImageFormat,
+    /// Whether frames which are unchanged compared to the `old` frames are saved to the `new` directory again.
+    ///
+    /// When disabled the `old` frame is hard linked into the `new` directory instead, falling back to a copy where hard
+    /// links aren't supported. The frame still has to end up in the `new` directory, as that becomes the baseline of
+    /// the next run. Only applies to tests without a snapshot which are compared to the `old` directory, and not when
+    /// a `compare_region` or `ignore_masks` could hide differences between the two frames.
+    pub write_unchanged_new: bool,
+    /// How much effort is spent on compressing frames saved as [OutputImageFormat::Png].
+    pub png_compression: CompressionTradeoff,
+    /// Write a hash of every frame next to it, allowing unchanged frames to be detected without decoding the `old` frame
+    /// or the snapshot.
+    ///
+    /// Snapshots only benefit from this if their sidecar was written by
+    /// [EmuTestRunner::accept_snapshots](crate::EmuTestRunner::accept_snapshots), sidecars older than their frame are
+    /// ignored.
+    pub hash_sidecars: bool,
+    /// How the frames of sequence tests are stored.
+    pub sequence_output: SequenceMode,
     /// Whenever a test has more than 1 produced image this will put those together in a sub-folder.
     pub put_sequence_tests_in_subfolder: bool,
     /// Put a copy of a comparison image in the failed/changed directory for easy comparison.
     pub copy_comparison_image: bool,
-    /// How long the entire test suite is allowed to take before the process is forcefully killed.
+    /// Keep the frames of failed tests in memory, as [TestOutputFailure::frames](crate::outputs::TestOutputFailure::frames).
+    ///
+    /// This allows a formatter to embed or encode the failing frames without reading them back from disk, at the cost of
+    /// keeping them resident until the [TestReport](crate::TestReport) is dropped. Frames of other outcomes are never kept.
+    pub retain_failure_frames: bool,
+    /// How long the entire test suite is allowed to take.
+    ///
+    /// Any test which hasn't completed once this time has elapsed is reported as an error, while the results of all
+    /// completed tests are still processed as usual. To be able to give up on a test, every test then runs on its own
+    /// thread, see [EmuTestRunner::run_tests](crate::EmuTestRunner::run_tests).
+    ///
+    /// In a config file this can be written as a string such as `"15s"` or `"500ms"`.
+    #[serde(with = "human_duration")]
     pub timeout: Option<Duration>,
+    /// How long a single test is allowed to take before it is reported as an error.
+    ///
+    /// A test exceeding this limit can't be forcefully stopped, its emulator thread is instead left to run in the
+    /// background while the rest of the suite continues. A genuinely stuck thread will thus keep consuming resources
+    /// until the process exits.
+    ///
+    /// Like [EmuRunnerOptions::timeout] this can be written as a string such as `"500ms"` in a config file.
+    #[serde(with = "human_duration")]
+    pub per_test_timeout: Option<Duration>,
+    /// How many times a test is re-ran after its emulator panicked or timed out, before it's reported as an error.
+    ///
+    /// The ROM is only read once, and the same data is passed to every attempt. Failed snapshot comparisons are not
+    /// retried, as those are only determined once all tests have been ran.
+    pub retries: usize,
+    /// Only compare this region of every frame to its snapshot, while still saving the full frame.
+    ///
+    /// Useful for emulators which render a border around the visible frame which varies between runs. Has to fit within
+    /// the `expected_frame_width` and `expected_frame_height`.
+    pub compare_region: Option<Rect>,
+    /// Regions of every frame which are ignored when comparing it to its snapshot, such as an in-game clock.
+    ///
+    /// Masked regions are blanked in both the frame and the snapshot before comparing, the saved frames are left as is.
+    /// Masks are applied before cropping to the `compare_region`, and are thus relative to the full frame.
+    pub ignore_masks: Vec<Rect>,
+    /// Allow small deviations from a snapshot to still count as a pass.
+    ///
+    /// When `None` frames have to be byte-for-byte identical to their snapshot.
+    pub tolerance: Option<PixelTolerance>,
+    /// Determine the outcome of every test without writing anything to disk.
+    ///
+    /// Frames are compared against the snapshots and the frames which would have been the `old` frames of a normal run,
+    /// resulting in the same [TestReport](crate::TestReport) categories. As no frames are saved the paths in the report
+    /// don't exist, and can thus not be passed to [EmuTestRunner::accept_snapshots](crate::EmuTestRunner::accept_snapshots).
+    pub dry_run: bool,
+    /// Stop starting new tests as soon as one test errors, or fails its snapshot comparison.
+    ///
+    /// Useful for quick checks such as pre-commit hooks, where the first failure is all that matters. Tests which are
+    /// already running still finish, and are reported as usual. Tests which were never started are counted as
+    /// [skipped](crate::RunSummary::skipped). Note that only snapshot failures stop the run, `changed` tests don't, even
+    /// with `treat_changes_as_failure` set.
+    pub fail_fast: bool,
+    /// The name of a variant of the emulator which needs its own snapshots, such as a rendering backend.
+    ///
+    /// Frames are compared to both the snapshot in the `{snapshot_path}/{snapshot_variant}` directory, and the shared
+    /// snapshot in the `snapshot_path` itself. A frame only fails if it matches neither, in which case the failure is
+    /// reported against the variant's snapshot if it exists. This allows only the tests which actually differ between
+    /// variants to have a variant specific snapshot.
+    pub snapshot_variant: Option<String>,
+    /// Enables caching of test outputs across runs, keyed on this version of the emulator.
+    ///
+    /// Tests whose ROM has the same contents as in the previous run reuse the frames that run saved in the `new`
+    /// directory, instead of running the emulator again. Change the version whenever the emulator itself changes, as
+    /// that invalidates the entire cache. Reused results are marked as
+    /// [cached](crate::outputs::TestOutputContext::cached) in the report.
+    pub emulator_version: Option<String>,
+    /// Whether tests which `changed` should cause [TestReport::is_success](crate::TestReport::is_success) to fail.
+    pub treat_changes_as_failure: bool,
+    /// Only compare frames to their snapshots, ignoring the output of previous runs entirely.
+    ///
+    /// Useful when the runner is purely used for regression gating, such as in CI. Sub-tests without a snapshot are
+    /// reported as [NoSnapshot](crate::outputs::TestOutputType::NoSnapshot) instead of `changed` or `unchanged`, and
+    /// passes and failures are never marked as new. The frames are still saved to the `new` directory, but neither the
+    /// `old` directory nor the `baseline` is read.
+    pub snapshot_only: bool,
+    /// Measure the peak memory usage of every test, reported as its
+    /// [peak_memory](crate::outputs::TestOutputContext::peak_memory).
+    ///
+    /// Only supported on Linux, where the peak resident set size of the process is read from `/proc/self/status` after
+    /// resetting it through `/proc/self/clear_refs` before every test. As this is a figure for the entire process, and
+    /// resetting it would affect the measurement of concurrently running tests, the tests have to run on a single thread:
+    /// with `num_threads` set to `1`, or a global pool of one thread if [EmuRunnerOptions::use_global_pool] is set. With
+    /// more threads, or on other platforms, no measurement is made.
+    pub measure_memory: bool,
+}
+
+/// The file formats supported by [EmuRunnerOptions::from_config_file].
+enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    fn of(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => anyhow::bail!("Config file {path:?} should have either a `.toml` or `.json` extension"),
+        }
+    }
+}
+
+/// A fluent way of creating [EmuRunnerOptions], starting from the [Default] options.
+///
+/// ```
+/// # use emu_test_runner::options::EmuRunnerOptions;
+/// let options = EmuRunnerOptions::builder()
+///     .output_path("./test_output")
+///     .frame_dimensions(160, 144)
+///     .num_threads(4)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(options.expected_frame_width, 160);
+/// assert!(EmuRunnerOptions::builder().num_threads(0).build().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct EmuRunnerOptionsBuilder {
+    options: EmuRunnerOptions,
+    num_threads: usize,
+    processing_threads: Option<usize>,
+}
+
+impl Default for EmuRunnerOptionsBuilder {
+    fn default() -> Self {
+        let options = EmuRunnerOptions::default();
+
+        Self {
+            num_threads: options.num_threads.get(),
+            processing_threads: options.processing_threads.map(NonZeroUsize::get),
+            options,
+        }
+    }
+}
+
+impl EmuRunnerOptionsBuilder {
+    /// Where the `new`, `old`, `changed`, and `failures` directories are created.
+    pub fn output_path(mut self, output_path: impl Into<PathBuf>) -> Self {
+        self.options.output_path = output_path.into();
+        self
+    }
+
+    /// See [EmuRunnerOptions::output_layout].
+    pub fn output_layout(mut self, output_layout: OutputLayout) -> Self {
+        self.options.output_layout = output_layout;
+        self
+    }
+
+    /// See [EmuRunnerOptions::baseline].
+    pub fn baseline(mut self, baseline: Baseline) -> Self {
+        self.options.baseline = baseline;
+        self
+    }
+
+    /// See [EmuRunnerOptions::rotate_output].
+    pub fn rotate_output(mut self, rotate_output: bool) -> Self {
+        self.options.rotate_output = rotate_output;
+        self
+    }
+
+    /// Where the snapshots the frames are compared to are read from.
+    pub fn snapshot_path(mut self, snapshot_path: impl Into<PathBuf>) -> Self {
+        self.options.snapshot_path = snapshot_path.into();
+        self
+    }
+
+    /// The amount of threads used to run the emulator, has to be at least `1`.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// See [EmuRunnerOptions::use_global_pool].
+    pub fn use_global_pool(mut self, use_global_pool: bool) -> Self {
+        self.options.use_global_pool = use_global_pool;
+        self
+    }
+
+    /// See [EmuRunnerOptions::processing_threads], has to be at least `1` if set.
+    pub fn processing_threads(mut self, processing_threads: impl Into<Option<usize>>) -> Self {
+        self.processing_threads = processing_threads.into();
+        self
+    }
+
+    /// The dimensions of the frames produced by the emulator, both have to be non-zero.
+    pub fn frame_dimensions(mut self, width: usize, height: usize) -> Self {
+        self.options.expected_frame_width = width;
+        self.options.expected_frame_height = height;
+        self
+    }
+
+    /// See [EmuRunnerOptions::frame_pixel_format].
+    pub fn frame_pixel_format(mut self, frame_pixel_format: PixelFormat) -> Self {
+        self.options.frame_pixel_format = frame_pixel_format;
+        self
+    }
+
+    /// See [EmuRunnerOptions::frame_preprocessor].
+    pub fn frame_preprocessor(mut self, preprocessor: impl Fn(&mut RgbaFrame) + Send + Sync + 'static) -> Self {
+        self.options.frame_preprocessor = Some(FramePreprocessor::new(preprocessor));
+        self
+    }
+
+    /// See [EmuRunnerOptions::output_image_format].
+    pub fn output_image_format(mut self, output_image_format: OutputImageFormat) -> Self {
+        self.options.output_image_format = output_image_format;
+        self
+    }
+
+    /// See [EmuRunnerOptions::write_unchanged_new].
+    pub fn write_unchanged_new(mut self, write_unchanged_new: bool) -> Self {
+        self.options.write_unchanged_new = write_unchanged_new;
+        self
+    }
+
+    /// See [EmuRunnerOptions::png_compression].
+    pub fn png_compression(mut self, png_compression: CompressionTradeoff) -> Self {
+        self.options.png_compression = png_compression;
+        self
+    }
+
+    /// See [EmuRunnerOptions::hash_sidecars].
+    pub fn hash_sidecars(mut self, hash_sidecars: bool) -> Self {
+        self.options.hash_sidecars = hash_sidecars;
+        self
+    }
+
+    /// See [EmuRunnerOptions::sequence_output].
+    pub fn sequence_output(mut self, sequence_output: SequenceMode) -> Self {
+        self.options.sequence_output = sequence_output;
+        self
+    }
+
+    /// See [EmuRunnerOptions::put_sequence_tests_in_subfolder].
+    pub fn put_sequence_tests_in_subfolder(mut self, put_in_subfolder: bool) -> Self {
+        self.options.put_sequence_tests_in_subfolder = put_in_subfolder;
+        self
+    }
+
+    /// See [EmuRunnerOptions::copy_comparison_image].
+    pub fn copy_comparison_image(mut self, copy_comparison_image: bool) -> Self {
+        self.options.copy_comparison_image = copy_comparison_image;
+        self
+    }
+
+    /// See [EmuRunnerOptions::retain_failure_frames].
+    pub fn retain_failure_frames(mut self, retain_failure_frames: bool) -> Self {
+        self.options.retain_failure_frames = retain_failure_frames;
+        self
+    }
+
+    /// See [EmuRunnerOptions::timeout], pass `None` to let the suite run indefinitely.
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.options.timeout = timeout.into();
+        self
+    }
+
+    /// See [EmuRunnerOptions::per_test_timeout].
+    pub fn per_test_timeout(mut self, per_test_timeout: impl Into<Option<Duration>>) -> Self {
+        self.options.per_test_timeout = per_test_timeout.into();
+        self
+    }
+
+    /// See [EmuRunnerOptions::retries].
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.options.retries = retries;
+        self
+    }
+
+    /// See [EmuRunnerOptions::compare_region].
+    pub fn compare_region(mut self, compare_region: impl Into<Option<Rect>>) -> Self {
+        self.options.compare_region = compare_region.into();
+        self
+    }
+
+    /// Add a region to the [EmuRunnerOptions::ignore_masks].
+    pub fn ignore_mask(mut self, mask: Rect) -> Self {
+        self.options.ignore_masks.push(mask);
+        self
+    }
+
+    /// See [EmuRunnerOptions::tolerance].
+    pub fn tolerance(mut self, tolerance: impl Into<Option<PixelTolerance>>) -> Self {
+        self.options.tolerance = tolerance.into();
+        self
+    }
+
+    /// See [EmuRunnerOptions::dry_run].
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    /// See [EmuRunnerOptions::fail_fast].
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.options.fail_fast = fail_fast;
+        self
+    }
+
+    /// See [EmuRunnerOptions::snapshot_variant].
+    pub fn snapshot_variant(mut self, snapshot_variant: impl Into<String>) -> Self {
+        self.options.snapshot_variant = Some(snapshot_variant.into());
+        self
+    }
+
+    /// See [EmuRunnerOptions::emulator_version].
+    pub fn emulator_version(mut self, emulator_version: impl Into<String>) -> Self {
+        self.options.emulator_version = Some(emulator_version.into());
+        self
+    }
+
+    /// See [EmuRunnerOptions::snapshot_only].
+    pub fn snapshot_only(mut self, snapshot_only: bool) -> Self {
+        self.options.snapshot_only = snapshot_only;
+        self
+    }
+
+    /// See [EmuRunnerOptions::treat_changes_as_failure].
+    pub fn treat_changes_as_failure(mut self, treat_changes_as_failure: bool) -> Self {
+        self.options.treat_changes_as_failure = treat_changes_as_failure;
+        self
+    }
+
+    /// See [EmuRunnerOptions::measure_memory].
+    pub fn measure_memory(mut self, measure_memory: bool) -> Self {
+        self.options.measure_memory = measure_memory;
+        self
+    }
+
+    /// Create the options, failing if they're invalid, see [EmuRunnerOptions::validate].
+    pub fn build(self) -> anyhow::Result<EmuRunnerOptions> {
+        let mut options = self.options;
+        options.num_threads = NonZeroUsize::new(self.num_threads).context("At least one thread is required")?;
+        options.processing_threads = self
+            .processing_threads
+            .map(|threads| NonZeroUsize::new(threads).context("At least one processing thread is required"))
+            .transpose()?;
+        options.validate()?;
+
+        Ok(options)
+    }
+}
+
+/// The names of the sub-directories of the output directory.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OutputLayout {
+    /// Contains the frames produced by the current run.
+    pub new_dir: String,
+    /// Contains the frames produced by the previous run.
+    pub old_dir: String,
+    /// Contains the frames of tests which changed compared to the previous run.
+    pub changed_dir: String,
+    /// Contains the frames of tests which no longer match their snapshot.
+    pub failures_dir: String,
+}
+
+impl OutputLayout {
+    pub fn new_path(&self, output: &Path) -> PathBuf {
+        output.join(&self.new_dir)
+    }
+
+    pub fn old_path(&self, output: &Path) -> PathBuf {
+        output.join(&self.old_dir)
+    }
+
+    pub fn changed_path(&self, output: &Path) -> PathBuf {
+        output.join(&self.changed_dir)
+    }
+
+    pub fn failures_path(&self, output: &Path) -> PathBuf {
+        output.join(&self.failures_dir)
+    }
+}
+
+impl Default for OutputLayout {
+    fn default() -> Self {
+        Self {
+            new_dir: NEW_DIR_NAME.to_string(),
+            old_dir: OLD_DIR_NAME.to_string(),
+            changed_dir: CHANGED_DIR_NAME.to_string(),
+            failures_dir: FAILED_DIR_NAME.to_string(),
+        }
+    }
+}
+
+/// The source of the frames a run is compared against to find `changed` tests, and tests which are newly passing or
+/// failing.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub enum Baseline {
+    /// The `old` output directory, containing the frames of the previous run.
+    #[default]
+    OldDirectory,
+    /// Frames committed to a git repository, read through `git show {reference}:{path}/{frame}`.
+    ///
+    /// The `path` is relative to the root of the repository, and is expected to be laid out like the `new` output
+    /// directory. Requires `git` to be available, and to be ran from within the repository. If the `reference` can't be
+    /// resolved every test results in an error, only frames missing from the `reference` count as newly added. As these
+    /// frames don't exist on disk a copy is always put in the `changed` directory for `changed` tests.
+    GitRef { reference: String, path: PathBuf },
+}
+
+/// The layout of the pixels in a framebuffer produced by the emulator.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Four bytes per pixel, in RGBA order.
+    Rgba8,
+    /// Three tightly packed bytes per pixel, in RGB order.
+    Rgb8,
+    /// One byte per pixel, describing its brightness.
+    Luma8,
+    /// One byte per pixel, describing an index into the given RGBA `palette`.
+    ///
+    /// Frames in this format are expanded to RGBA before they're saved and compared.
+    Indexed { palette: Vec<[u8; 4]> },
+}
+
+impl PixelFormat {
+    /// The amount of bytes a single pixel occupies in this format.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Luma8 | PixelFormat::Indexed { .. } => 1,
+        }
+    }
+}
+
+/// The file format used to store frames on disk.
+///
+/// Encoding is a significant part of the runtime of large suites, where [OutputImageFormat::Qoi] tends to be much
+/// faster than [OutputImageFormat::Png] for typical frames.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputImageFormat {
+    #[default]
+    Png,
+    Bmp,
+    /// Only supports RGB and RGBA frames, and thus can't be used with [PixelFormat::Luma8].
+    Qoi,
+    /// Lossless WebP, which is usually considerably smaller than PNG but slower to encode.
+    ///
+    /// Useful when the output directories are archived, for example as CI artifacts. Frames are always encoded
+    /// losslessly, as lossy frames would never match their snapshot exactly.
+    WebP,
+    /// The unencoded pixel data, laid out as described by the [PixelFormat] of the frames.
+    ///
+    /// As these files contain no header they can only be read back if the frame dimensions and [PixelFormat] are the
+    /// same as when they were written.
+    Raw,
+}
+
+impl OutputImageFormat {
+    /// The file extension used for frames saved in this format, without a leading dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputImageFormat::Png => "png",
+            OutputImageFormat::Bmp => "bmp",
+            OutputImageFormat::Qoi => "qoi",
+            OutputImageFormat::WebP => "webp",
+            OutputImageFormat::Raw => "bin",
+        }
+    }
+
+    /// The matching encoder of the `image` crate, `None` for [OutputImageFormat::Raw].
+    pub(crate) fn image_format(&self) -> Option<image::ImageFormat> {
+        match self {
+            OutputImageFormat::Png => Some(image::ImageFormat::Png),
+            OutputImageFormat::Bmp => Some(image::ImageFormat::Bmp),
+            OutputImageFormat::Qoi => Some(image::ImageFormat::Qoi),
+            OutputImageFormat::WebP => Some(image::ImageFormat::WebP),
+            OutputImageFormat::Raw => None,
+        }
+    }
+}
+
+/// The trade-off between encoding speed and file size when saving frames as [OutputImageFormat::Png].
+///
+/// Encoding PNGs can make up a significant part of the runtime of large suites, while the size of the frames rarely
+/// matters for snapshot tests. The compression level never affects the outcome of a comparison, only the pixels do.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionTradeoff {
+    /// Spend as little time as possible on compression, at the cost of larger files.
+    Fast,
+    /// The default compression level of the `image` crate.
+    #[default]
+    Default,
+    /// Produce the smallest files, at the cost of slower encoding.
+    Best,
+}
+
+impl CompressionTradeoff {
+    pub(crate) fn png_compression(&self) -> image::codecs::png::CompressionType {
+        match self {
+            CompressionTradeoff::Fast => image::codecs::png::CompressionType::Fast,
+            CompressionTradeoff::Default => image::codecs::png::CompressionType::default(),
+            CompressionTradeoff::Best => image::codecs::png::CompressionType::Best,
+        }
+    }
+}
+
+/// How the frames produced by a sequence test are stored and compared.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SequenceMode {
+    /// Every frame is stored in its own file in the [OutputImageFormat], and compared to its own snapshot.
+    #[default]
+    Separate,
+    /// All frames are stored, in order, as a single animated `{rom_id}.gif`, which is compared to a snapshot GIF as a
+    /// whole.
+    ///
+    /// A sequence test then results in a single sub-test. Note that GIFs are limited to 256 colours per frame, frames
+    /// with more colours are thus compared after being reduced to 256 colours.
+    AnimatedGif,
+}
+
+/// A function which normalises a framebuffer before it is compared, see [EmuRunnerOptions::frame_preprocessor].
+///
+/// Can be used to, for example, convert BGRA framebuffers to RGBA, or to crop away a border.
+#[derive(Clone)]
+pub struct FramePreprocessor(pub Arc<dyn Fn(&mut RgbaFrame) + Send + Sync>);
+
+impl FramePreprocessor {
+    pub fn new(preprocessor: impl Fn(&mut RgbaFrame) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(preprocessor))
+    }
+}
+
+impl Debug for FramePreprocessor {
+    fn fmt(&self, f: &mut Formatter) -> ::core::fmt::Result {
+        Formatter::debug_tuple(f, "FramePreprocessor").finish_non_exhaustive()
+    }
+}
+
+/// A rectangular region of a frame, in pixels.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Display for Rect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{} at ({}, {})", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Describes how much a frame may deviate from its snapshot before it is considered a failure.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PixelTolerance {
+    /// The maximum absolute difference allowed for any single colour channel before a pixel counts as differing.
+    pub max_channel_delta: u8,
+    /// The maximum fraction (in the range `[0, 1]`) of pixels which may differ before the frame counts as differing.
+    pub max_differing_fraction: f32,
+}
+
+impl EmuRunnerOptions {
+    /// Start building options from the [Default] options.
+    pub fn builder() -> EmuRunnerOptionsBuilder {
+        EmuRunnerOptionsBuilder::default()
+    }
+
+    /// Load options from a TOML or JSON config file, depending on its extension, and validate them.
+    ///
+    /// Fields missing from the file take their [Default] value.
+    pub fn from_config_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let format = ConfigFormat::of(path)?;
+        let contents = std::fs::read_to_string(path).with_context(|| format!("Couldn't read config file {path:?}"))?;
+
+        let options: Self = match format {
+            ConfigFormat::Toml => toml::from_str(&contents)?,
+            ConfigFormat::Json => serde_json::from_str(&contents)?,
+        };
+        options
+            .validate()
+            .with_context(|| format!("Invalid options in config file {path:?}"))?;
+
+        Ok(options)
+    }
+
+    /// Write these options to a TOML or JSON config file, depending on its extension.
+    ///
+    /// Note that the [EmuRunnerOptions::frame_preprocessor] can't be stored, and is thus not part of the file.
+    pub fn write_config_file(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let contents = match ConfigFormat::of(path)? {
+            ConfigFormat::Toml => toml::to_string_pretty(self)?,
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+        };
+
+        std::fs::write(path, contents).with_context(|| format!("Couldn't write config file {path:?}"))
+    }
+
+    /// Check whether these options are usable, returning a description of the first problem found otherwise.
+    ///
+    /// Called by [EmuTestRunner::new](crate::EmuTestRunner::new), so that a misconfiguration is reported once instead of
+    /// as an error for every single test.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let (width, height) = (self.expected_frame_width, self.expected_frame_height);
+
+        if width == 0 || height == 0 {
+            anyhow::bail!("Expected frame dimensions of {width}x{height} are invalid, both have to be non-zero");
+        }
+
+        if u32::try_from(width).is_err() || u32::try_from(height).is_err() {
+            anyhow::bail!("Expected frame dimensions of {width}x{height} are too large");
+        }
+
+        if let PixelFormat::Indexed { palette } = &self.frame_pixel_format {
+            if palette.is_empty() {
+                anyhow::bail!("The palette of the indexed frame pixel format is empty");
+            }
+        }
+
+        let regions = self
+            .compare_region
+            .iter()
+            .map(|region| ("Compare region", region))
+            .chain(self.ignore_masks.iter().map(|mask| ("Ignore mask", mask)));
+
+        for (name, region) in regions {
+            if region.width == 0 || region.height == 0 {
+                anyhow::bail!("{name} {region} is empty, both its width and height have to be non-zero");
+            }
+
+            let fits = |offset: u32, length: u32, max: usize| offset as u64 + length as u64 <= max as u64;
+            if !fits(region.x, region.width, width) || !fits(region.y, region.height, height) {
+                anyhow::bail!("{name} {region} doesn't fit within the expected frame dimensions of {width}x{height}");
+            }
+        }
+
+        if self.output_image_format == OutputImageFormat::Qoi && self.frame_pixel_format == PixelFormat::Luma8 {
+            anyhow::bail!("Luma8 frames can't be saved as Qoi, which only supports RGB and RGBA images");
+        }
+
+        if let Some(variant) = &self.snapshot_variant {
+            if variant.is_empty() || Path::new(variant).is_absolute() {
+                anyhow::bail!("Snapshot variant `{variant}` has to be a non-empty relative directory name");
+            }
+        }
+
+        if let Some(tolerance) = &self.tolerance {
+            if !(0.0..=1.0).contains(&tolerance.max_differing_fraction) {
+                anyhow::bail!(
+                    "Tolerance `max_differing_fraction` of {} is outside of the range [0, 1]",
+                    tolerance.max_differing_fraction
+                );
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for EmuRunnerOptions {
     fn default() -> Self {
         Self {
             output_path: PathBuf::from("./test_output"),
+            output_layout: OutputLayout::default(),
+            baseline: Baseline::OldDirectory,
+            rotate_output: true,
             snapshot_path: PathBuf::from("./test_roms/expected"),
-            num_threads: std::thread::available_parallelism().expect("Couldn't get available threads"),
+            num_threads: std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            use_global_pool: false,
+            processing_threads: None,
             expected_frame_width: 240,
             expected_frame_height: 160,
+            frame_pixel_format: PixelFormat::Rgba8,
+            frame_preprocessor: None,
+            output_image_format: OutputImageFormat::Png,
+            write_unchanged_new: true,
+            png_compression: CompressionTradeoff::Default,
+            hash_sidecars: false,
+            sequence_output: SequenceMode::Separate,
             put_sequence_tests_in_subfolder: true,
             copy_comparison_image: true,
+            retain_failure_frames: false,
             timeout: Some(Duration::from_secs(15)),
+            per_test_timeout: None,
+            retries: 0,
+            compare_region: None,
+            ignore_masks: Vec::new(),
+            tolerance: None,
+            snapshot_variant: None,
+            emulator_version: None,
+            treat_changes_as_failure: false,
+            snapshot_only: false,
+            measure_memory: false,
+            dry_run: false,
+            fail_fast: false,
+        }
+    }
+}
+
+/// (De)serialization of optional [Duration]s as human-friendly strings such as `"15s"` or `"500ms"`.
+///
+/// The `{secs, nanos}` struct form which serde uses by default is still accepted when deserializing.
+mod human_duration {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// The supported units, with the amount of nanoseconds in each.
+    const UNITS: [(&str, u128); 7] = [
+        ("ns", 1),
+        ("us", 1_000),
+        ("µs", 1_000),
+        ("ms", 1_000_000),
+        ("s", 1_000_000_000),
+        ("m", 60 * 1_000_000_000),
+        ("h", 60 * 60 * 1_000_000_000),
+    ];
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DurationRepr {
+        Text(String),
+        Struct(Duration),
+    }
+
+    pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_str(&format_duration(*duration)),
+            None => serializer.serialize_none(),
         }
     }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        match Option::<DurationRepr>::deserialize(deserializer)? {
+            Some(DurationRepr::Text(text)) => parse_duration(&text).map(Some).map_err(serde::de::Error::custom),
+            Some(DurationRepr::Struct(duration)) => Ok(Some(duration)),
+            None => Ok(None),
+        }
+    }
+
+    /// Format the `duration` in the largest of `s`, `ms`, `us`, or `ns` which represents it exactly.
+    fn format_duration(duration: Duration) -> String {
+        let nanos = duration.as_nanos();
+
+        [("s", 1_000_000_000), ("ms", 1_000_000), ("us", 1_000)]
+            .into_iter()
+            .find(|(_, per_unit)| nanos.is_multiple_of(*per_unit))
+            .map(|(unit, per_unit)| format!("{}{unit}", nanos / per_unit))
+            .unwrap_or_else(|| format!("{nanos}ns"))
+    }
+
+    /// Parse a duration such as `"15s"`, `"1.5m"`, or `"500ms"`.
+    fn parse_duration(text: &str) -> Result<Duration, String> {
+        let text = text.trim();
+        let amount = text.trim_end_matches(char::is_alphabetic);
+        let (amount, unit) = (amount.trim_end(), &text[amount.len()..]);
+        if unit.is_empty() {
+            return Err(format!(
+                "Duration `{text}` is missing a unit, expected one of ns, us, ms, s, m, or h"
+            ));
+        }
+
+        let (_, per_unit) = UNITS.iter().find(|(name, _)| *name == unit).ok_or_else(|| {
+            format!("Duration `{text}` has unknown unit `{unit}`, expected one of ns, us, ms, s, m, or h")
+        })?;
+
+        let nanos = match amount.parse::<u128>() {
+            Ok(amount) => amount.checked_mul(*per_unit),
+            Err(_) => match amount.parse::<f64>() {
+                // Casting to an integer would silently saturate a negative amount to zero.
+                Ok(amount) if amount < 0.0 => return Err(format!("Duration `{text}` can't be negative")),
+                Ok(amount) if amount.is_finite() => Some((amount * *per_unit as f64).round() as u128),
+                _ => None,
+            },
+        }
+        .ok_or_else(|| format!("Duration `{text}` doesn't have a valid amount"))?;
+
+        let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| format!("Duration `{text}` is too large"))?;
+        Ok(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn invalid_amounts_are_rejected() {
+            assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+            assert_eq!(
+                parse_duration("-5s"),
+                Err("Duration `-5s` can't be negative".to_string())
+            );
+            assert!(parse_duration("inf s").is_err());
+            assert!(parse_duration("NaN ms").is_err());
+            assert!(toml::from_str::<crate::options::EmuRunnerOptions>(r#"timeout = "-5s""#).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luma_frames_cant_be_saved_as_qoi() {
+        let options = |frame_pixel_format, output_image_format| EmuRunnerOptions {
+            frame_pixel_format,
+            output_image_format,
+            ..Default::default()
+        };
+
+        assert!(options(PixelFormat::Luma8, OutputImageFormat::Qoi).validate().is_err());
+        assert!(options(PixelFormat::Luma8, OutputImageFormat::Png).validate().is_ok());
+        assert!(options(PixelFormat::Rgb8, OutputImageFormat::Qoi).validate().is_ok());
+    }
 }
@@ -1,4 +1,6 @@
-use std::fmt::{Debug, Formatter};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
@@ -12,14 +14,18 @@ pub type TestUnchanged = EmuContext<TestOutputContext<TestOutputUnchanged>>;
 pub type TestFailed = EmuContext<TestOutputContext<TestOutputFailure>>;
 pub type TestError = EmuContext<TestOutputError>;
 pub type TestChanged = EmuContext<TestOutputContext<TestOutputChanged>>;
+pub type TestNoSnapshot = EmuContext<TestOutputContext<TestOutputNoSnapshot>>;
 
-pub type RunnerError = EmuContext<anyhow::Error>;
+pub type RunnerError = EmuContext<RunnerErrorContext>;
 /// One [RunnerOutput] is a single test, with potentially multiple sub-tests due to being a sequence-test.
 pub type RunnerOutput = EmuContext<RunnerOutputContext>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct EmuContext<T> {
-    pub candidate: TestCandidate,
+    /// The test this context belongs to, shared between all sub-tests of a single test.
+    #[serde(flatten)]
+    pub candidate: Arc<TestCandidate>,
+    #[serde(flatten)]
     pub context: T,
 }
 
@@ -39,53 +45,348 @@ impl<T> EmuContext<T> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TestOutputContext<T> {
     pub time_taken: Option<Duration>,
+    /// The locations associated with the frame of this sub-test.
+    ///
+    /// Will be `None` if the test failed to produce any frames.
+    pub frame: Option<FrameLocation>,
+    /// The [EmulatorOutput::metadata] returned by the emulator, shared between all sub-tests of a single test.
+    #[serde(skip_serializing_if = "is_empty_metadata")]
+    pub metadata: Arc<HashMap<String, String>>,
+    /// Whether the frames of this sub-test were reused from a previous run instead of running the emulator.
+    ///
+    /// See [EmuRunnerOptions::emulator_version](crate::options::EmuRunnerOptions::emulator_version).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// The peak memory usage in bytes while running the emulator, shared between all sub-tests of a single test.
+    ///
+    /// Only present if [EmuRunnerOptions::measure_memory](crate::options::EmuRunnerOptions::measure_memory) is enabled
+    /// on a supported platform with a single test thread, and the emulator was actually ran.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peak_memory: Option<u64>,
+    #[serde(flatten)]
     pub output: T,
 }
 
-#[derive(Debug, Clone)]
+/// The locations on disk associated with a single frame produced by a test.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FrameLocation {
+    /// The tag of the [FrameOutput] this location belongs to.
+    pub tag: Option<String>,
+    /// Where the frame was saved in the `new` output directory.
+    pub new_path: PathBuf,
+    /// Where the snapshot for this frame is (or would be) located.
+    pub snapshot_path: PathBuf,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
 pub enum TestOutputType {
     Unchanged(TestOutputUnchanged),
     Changed(TestOutputChanged),
     Failure(TestOutputFailure),
     Passed(TestOutputPassed),
     Error(TestOutputError),
+    /// The sub-test has no snapshot, and is thus neither `passed` nor `failed`.
+    ///
+    /// Only produced with [snapshot_only](crate::options::EmuRunnerOptions::snapshot_only), otherwise such sub-tests are
+    /// compared to the previous run and end up `unchanged` or `changed` instead.
+    NoSnapshot(TestOutputNoSnapshot),
 }
 
-#[derive(Debug, Clone)]
+impl TestOutputType {
+    /// The name of this outcome, matching the `outcome` field of its serialized form.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TestOutputType::Unchanged(_) => "unchanged",
+            TestOutputType::Changed(_) => "changed",
+            TestOutputType::Failure(_) => "failure",
+            TestOutputType::Passed(_) => "passed",
+            TestOutputType::Error(_) => "error",
+            TestOutputType::NoSnapshot(_) => "no_snapshot",
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TestOutputUnchanged {
     pub newly_added: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TestOutputPassed {
     pub is_new: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TestOutputNoSnapshot {}
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TestOutputFailure {
     pub failure_path: PathBuf,
+    /// The snapshot the failure is reported against, the most preferred one if several were tried.
     pub snapshot_path: PathBuf,
+    /// How many acceptable snapshots the output was compared to, more than `1` if the snapshot is a directory of
+    /// alternatives or with a [snapshot_variant](crate::options::EmuRunnerOptions::snapshot_variant).
+    pub snapshots_tried: usize,
     pub is_new: bool,
+    /// The output of the previous run in the `old` directory, if it exists.
+    ///
+    /// Always `None` with a git [baseline](crate::options::EmuRunnerOptions::baseline), which isn't on disk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<PathBuf>,
+    #[serde(flatten)]
+    pub kind: FailureKind,
+    /// The failing frames as returned by the emulator (after preprocessing), in order.
+    ///
+    /// Only kept for frame failures if [retain_failure_frames](crate::options::EmuRunnerOptions::retain_failure_frames)
+    /// is set, and always `None` for text failures.
+    #[serde(skip)]
+    pub frames: Option<Arc<[RgbaFrame]>>,
 }
 
-#[derive(Debug, Clone)]
+/// What differed between the output of a test and its snapshot.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "failure_kind", rename_all = "snake_case")]
+pub enum FailureKind {
+    /// The frames of the test didn't match the snapshot.
+    Image {
+        /// The amount of pixels which differed from the snapshot by more than the allowed tolerance.
+        ///
+        /// If the dimensions of the frame and snapshot differ every pixel of the frame counts as differing.
+        differing_pixels: usize,
+        /// The largest difference found in any single colour channel.
+        max_channel_delta: u8,
+    },
+    /// The dimensions of a frame differed from its snapshot, its contents were not compared.
+    ///
+    /// Usually caused by a change in the output resolution of the emulator.
+    Dimensions {
+        snapshot_width: u32,
+        snapshot_height: u32,
+        frame_width: u32,
+        frame_height: u32,
+    },
+    /// The [EmulatorOutput::text] of the test didn't match the text snapshot.
+    Text { expected: String, actual: String },
+    /// The comparator passed to [EmuTestRunner::with_comparator](crate::EmuTestRunner::with_comparator) rejected the
+    /// frames, for the given reason.
+    Custom { reason: String },
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureKind::Image {
+                differing_pixels,
+                max_channel_delta,
+            } => write!(
+                f,
+                "{differing_pixels} pixels differ from the snapshot, by at most {max_channel_delta} per channel"
+            ),
+            FailureKind::Dimensions {
+                snapshot_width,
+                snapshot_height,
+                frame_width,
+                frame_height,
+            } => write!(
+                f,
+                "snapshot dimensions {snapshot_width}x{snapshot_height} but frame is {frame_width}x{frame_height}"
+            ),
+            FailureKind::Text { .. } => write!(f, "text output differs from the snapshot"),
+            FailureKind::Custom { reason } => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// The verdict of a custom comparator, see [EmuTestRunner::with_comparator](crate::EmuTestRunner::with_comparator).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    /// The frame is close enough to the snapshot for the test to pass.
+    Match,
+    /// The frame doesn't match the snapshot, the `reason` is reported as part of the failure.
+    Mismatch { reason: String },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TestOutputChanged {
     pub changed_path: PathBuf,
     pub old_path: PathBuf,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct TestOutputError {
+    /// What caused the test to fail before producing any frames.
+    ///
+    /// Will be `None` if the error instead occurred while processing the produced frames.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<RunnerErrorKind>,
+    #[serde(serialize_with = "serialize_error")]
     pub reason: Arc<anyhow::Error>,
 }
 
+impl TestOutputError {
+    /// The emulator panic which caused this error, if any.
+    pub fn panic(&self) -> Option<&EmulatorPanic> {
+        self.reason.downcast_ref()
+    }
+}
+
+fn serialize_error<S: serde::Serializer>(error: &Arc<anyhow::Error>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{error:#}"))
+}
+
+#[derive(Debug)]
+pub struct RunnerErrorContext {
+    pub kind: RunnerErrorKind,
+    pub error: anyhow::Error,
+}
+
+/// The cause of a test failing before it could produce any frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunnerErrorKind {
+    /// The ROM of the test couldn't be read.
+    IoRead,
+    /// The emulator panicked, the error will contain an [EmulatorPanic].
+    EmulatorPanic,
+    /// The test didn't complete in time, the error will contain a [TimeoutError].
+    Timeout,
+    /// Any other error, such as being unable to spawn a thread for the emulator.
+    Other,
+}
+
+impl RunnerErrorKind {
+    /// Determine the kind of an error returned while running the emulator.
+    pub(crate) fn of_emulator_error(error: &anyhow::Error) -> Self {
+        if error.is::<EmulatorPanic>() {
+            RunnerErrorKind::EmulatorPanic
+        } else if error.is::<TimeoutError>() {
+            RunnerErrorKind::Timeout
+        } else {
+            RunnerErrorKind::Other
+        }
+    }
+}
+
+/// The error reported for a test whose emulator panicked.
+///
+/// Can be retrieved from a [RunnerError] or [TestOutputError] through [anyhow::Error::downcast_ref].
+#[derive(Debug)]
+pub struct EmulatorPanic {
+    pub message: String,
+    /// The source location of the panic as `file:line:column`, which is always captured unlike the `backtrace`.
+    pub location: Option<String>,
+    /// The backtrace of the panic, only captured if enabled through the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`
+    /// environment variables.
+    pub backtrace: Backtrace,
+}
+
+impl Display for EmulatorPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "Caught an emulator panic at {location}: `{}`", self.message)?,
+            None => write!(f, "Caught an emulator panic: `{}`", self.message)?,
+        }
+
+        if self.backtrace.status() == BacktraceStatus::Captured {
+            write!(f, "\n\nPanic backtrace:\n{}", self.backtrace)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for EmulatorPanic {}
+
+/// The error reported for a test which could not complete in the allotted time.
+///
+/// Can be retrieved from a [RunnerError] or [TestOutputError] through [anyhow::Error::downcast_ref].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutError {
+    /// The entire test suite exceeded [EmuRunnerOptions::timeout](crate::options::EmuRunnerOptions::timeout) before
+    /// this test could complete.
+    Suite { limit: Duration },
+    /// This test exceeded [EmuRunnerOptions::per_test_timeout](crate::options::EmuRunnerOptions::per_test_timeout).
+    Test { limit: Duration },
+}
+
+impl Display for TimeoutError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeoutError::Suite { limit } => write!(f, "Test suite exceeded its timeout of {limit:?}"),
+            TimeoutError::Test { limit } => write!(f, "Test exceeded per-test timeout of {limit:?}"),
+        }
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
 #[derive(Debug)]
 pub struct RunnerOutputContext {
+    /// The time taken by the final, successful, attempt.
     pub time_taken: Duration,
     pub frame_output: Vec<FrameOutput>,
+    /// The [EmulatorOutput::metadata] returned by the emulator.
+    pub metadata: Arc<HashMap<String, String>>,
+    /// The [EmulatorOutput::text] returned by the emulator.
+    pub text: Option<String>,
+    /// How many times the emulator had to be ran before it completed, at least `1` unless the output was `cached`.
+    pub attempts: usize,
+    /// Whether the output was reused from a previous run, in which case the emulator wasn't ran at all.
+    pub cached: bool,
+    /// The peak memory usage in bytes of the final attempt, see [TestOutputContext::peak_memory].
+    pub peak_memory: Option<u64>,
+}
+
+/// Everything produced by a single run of the emulator.
+///
+/// `emu_run` can return either this, or just the [FrameOutput]s of the test if there is no metadata to report.
+#[derive(Debug, Default)]
+pub struct EmulatorOutput {
+    pub frames: Vec<FrameOutput>,
+    /// Arbitrary side information about the run, such as the cycles executed or any serial output.
+    ///
+    /// This is not compared against any snapshot, but is passed along to the formatters so that it can be asserted on.
+    pub metadata: HashMap<String, String>,
+    /// Text output of the run, such as the serial output of a test ROM which reports its result over serial.
+    ///
+    /// If present, it is compared against a `{rom_id}.txt` snapshot as an additional sub-test.
+    pub text: Option<String>,
+}
+
+impl EmulatorOutput {
+    pub fn new(frames: Vec<FrameOutput>) -> Self {
+        Self {
+            frames,
+            metadata: HashMap::new(),
+            text: None,
+        }
+    }
+
+    /// Attach `text` output to this run, to be compared against the test's text snapshot.
+    pub fn with_text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    /// Attach a single `key`/`value` pair of metadata to this output.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl From<Vec<FrameOutput>> for EmulatorOutput {
+    fn from(frames: Vec<FrameOutput>) -> Self {
+        Self::new(frames)
+    }
+}
+
+fn is_empty_metadata(metadata: &Arc<HashMap<String, String>>) -> bool {
+    metadata.is_empty()
 }
 
 /// The output produced by a test.
@@ -93,17 +394,22 @@ pub struct RunnerOutputContext {
 /// A test can produce multiple instances of `FrameOutput`. This marks the test as a `sequence` test.
 /// This can be useful if you need to perform some inputs on your test rom, and want to periodically make `FrameOutputs` to
 /// ensure the intermediate results look correct as well.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FrameOutput {
+    /// Used to name the image of this frame, `{rom_id}_{tag}`.
+    ///
+    /// Untagged frames of a sequence test are instead named after their index in the sequence, `{rom_id}_{index}`.
     pub tag: Option<String>,
     pub frame: RgbaFrame,
 }
 
 /// A single frame from the emulator, with the implicit assumption that:
 ///
-/// `frame.len() == emu.FRAME_WIDTH * emu.FRAME_HEIGHT`
+/// `frame.len() == emu.FRAME_WIDTH * emu.FRAME_HEIGHT * bytes_per_pixel`
 ///
-/// Bytes are expected in RGBA format, so one pixel is 32 bits.
+/// Bytes are expected in the [PixelFormat](crate::options::PixelFormat) configured in the runner's options, which is
+/// RGBA (32 bits per pixel) by default.
+#[derive(Clone)]
 pub struct RgbaFrame(pub Vec<u8>);
 
 impl Debug for RgbaFrame {
@@ -1,28 +1,53 @@
-use std::collections::HashMap;
-use std::sync::Mutex;
-use std::thread::ThreadId;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::panic::AssertUnwindSafe;
 
-use once_cell::sync::Lazy;
-
-pub static PANIC_BUFFER: Lazy<Mutex<HashMap<ThreadId, Vec<PanicCorrelation>>>> = Lazy::new(Mutex::default);
+thread_local! {
+    /// The panics recorded by the hook of [run_in_custom_handler] on this thread.
+    ///
+    /// Panic hooks always run on the panicking thread, so keeping the buffer thread-local means the hook never has to
+    /// contend for (or deadlock on) a lock shared with other threads.
+    static PANIC_BUFFER: RefCell<Vec<PanicCorrelation>> = const { RefCell::new(Vec::new()) };
+    /// The amount of [PanicMark]s on this thread which haven't been passed to [take_panic_since] yet.
+    ///
+    /// Panics are only recorded while a mark is active, as nothing would ever drain them from the buffer otherwise.
+    static MARK_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
 
 #[derive(Debug)]
 pub struct PanicCorrelation {
-    panic_msg: String,
+    pub panic_msg: String,
+    /// The source location of the panic, as `file:line:column`.
+    pub location: Option<String>,
+    /// Only captured if enabled through the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables.
+    pub backtrace: Backtrace,
+}
+
+/// A position in the panic buffer of a single thread, see [panic_mark].
+#[derive(Debug)]
+pub struct PanicMark(usize);
+
+/// Mark the current position in the caller's thread panic buffer.
+///
+/// Any panic recorded after this point can be retrieved with [take_panic_since], which ensures panics recorded for
+/// earlier (or concurrent) work on the same thread are never attributed to the wrong caller.
+///
+/// Panics are only recorded while a mark is active, so every mark must be passed to [take_panic_since].
+pub fn panic_mark() -> PanicMark {
+    MARK_DEPTH.set(MARK_DEPTH.get() + 1);
+    PanicMark(PANIC_BUFFER.with_borrow(|panics| panics.len()))
 }
 
-/// Returns the message of the most recent panic on the caller's thread.
+/// Removes all panics recorded on the caller's thread since the given `mark`, and returns the last one.
+///
+/// The last panic is the one which ended up unwinding, any earlier panics must have been caught along the way.
 ///
 /// # Returns
 ///
-/// The message of the latest panic
-pub fn latest_panic() -> Option<String> {
-    let thread = std::thread::current().id();
-    let mut buffer = PANIC_BUFFER.lock().ok()?;
-    let new_buffer = buffer.get_mut(&thread)?;
-
-    let item = new_buffer.last_mut()?;
-    Some(item.panic_msg.clone())
+/// The latest panic since `mark`
+pub fn take_panic_since(mark: PanicMark) -> Option<PanicCorrelation> {
+    MARK_DEPTH.set(MARK_DEPTH.get().saturating_sub(1));
+    PANIC_BUFFER.with_borrow_mut(|panics| panics.drain(mark.0.min(panics.len())..).next_back())
 }
 
 /// Run the given closure in a custom panic handler which saves the panic message for later correlation
@@ -30,12 +55,19 @@ pub fn latest_panic() -> Option<String> {
 ///
 /// Note that [std::panic::catch_unwind] is still required to be able to correlate the panic, as otherwise the thread
 /// will have died and no correlation would be possible any more.
+///
+/// The previous panic hook is restored afterwards, even if the `function` unwinds.
 pub fn run_in_custom_handler<R>(function: impl FnOnce() -> R) -> R {
     let hook = std::panic::take_hook();
 
     std::panic::set_hook({
         Box::new(move |info| {
-            let mut global_buffer = PANIC_BUFFER.lock().unwrap();
+            // Without an active mark nobody would take the panic out of the buffer again. This also covers threads
+            // which are being torn down, for which the thread-local is no longer accessible.
+            if MARK_DEPTH.try_with(|depth| depth.get()).unwrap_or(0) == 0 {
+                return;
+            }
+
             let msg = match info.payload().downcast_ref::<&'static str>() {
                 Some(s) => *s,
                 None => match info.payload().downcast_ref::<String>() {
@@ -44,18 +76,115 @@ pub fn run_in_custom_handler<R>(function: impl FnOnce() -> R) -> R {
                 },
             };
 
-            let thread = std::thread::current();
             let correlation = PanicCorrelation {
                 panic_msg: msg.to_string(),
+                location: info.location().map(|location| location.to_string()),
+                backtrace: Backtrace::capture(),
             };
-            let nested = global_buffer.entry(thread.id()).or_default();
-            nested.push(correlation);
+            // Panicking inside the hook would abort, so a panic while the buffer is borrowed simply goes unrecorded.
+            let _ = PANIC_BUFFER.try_with(|panics| {
+                if let Ok(mut panics) = panics.try_borrow_mut() {
+                    panics.push(correlation);
+                }
+            });
         })
     });
 
-    let out = function();
+    // The hook can't be replaced while the thread is panicking, so a `Drop` guard wouldn't be able to restore it during
+    // an unwind. Instead the unwind is caught, and resumed once the original hook is back in place. The closure is
+    // never observed in a broken state, as the unwind continues right away.
+    let out = std::panic::catch_unwind(AssertUnwindSafe(function));
 
     std::panic::set_hook(hook);
 
-    out
+    out.unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+}
+
+/// Serialises tests which (indirectly) replace the panic hook, as it is global to the process.
+#[cfg(test)]
+pub fn hook_lock() -> std::sync::MutexGuard<'static, ()> {
+    static HOOK_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    HOOK_LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn previous_hook_is_restored_after_unwind() {
+        let _lock = hook_lock();
+        let original = std::panic::take_hook();
+        let marker_fired = Arc::new(AtomicBool::new(false));
+
+        std::panic::set_hook({
+            let marker_fired = marker_fired.clone();
+            Box::new(move |_| marker_fired.store(true, Ordering::SeqCst))
+        });
+
+        let result = std::panic::catch_unwind(|| run_in_custom_handler(|| panic!("inside the handler")));
+        let fired_inside = marker_fired.load(Ordering::SeqCst);
+
+        let _ = std::panic::catch_unwind(|| panic!("after the handler"));
+        let fired_after = marker_fired.load(Ordering::SeqCst);
+
+        std::panic::set_hook(original);
+
+        assert!(result.is_err());
+        assert!(!fired_inside, "the custom handler should have replaced the marker hook");
+        assert!(fired_after, "the marker hook should have been restored");
+    }
+
+    #[test]
+    fn concurrent_panics_are_attributed_to_their_own_mark() {
+        let _lock = hook_lock();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(16).build().unwrap();
+
+        let messages: Vec<_> = run_in_custom_handler(|| {
+            pool.install(|| {
+                (0..512)
+                    .into_par_iter()
+                    .map(|i| {
+                        let mark = panic_mark();
+                        let _ = std::panic::catch_unwind(|| panic!("storm {i}"));
+                        (i, take_panic_since(mark).map(|panic| panic.panic_msg))
+                    })
+                    .collect()
+            })
+        });
+
+        for (i, message) in messages {
+            assert_eq!(message, Some(format!("storm {i}")));
+        }
+    }
+
+    #[test]
+    fn panics_without_a_mark_are_not_recorded() {
+        let _lock = hook_lock();
+
+        run_in_custom_handler(|| {
+            let _ = std::panic::catch_unwind(|| panic!("unmarked"));
+        });
+
+        assert!(PANIC_BUFFER.with_borrow(|panics| panics.is_empty()));
+    }
+
+    #[test]
+    fn buffer_is_drained_after_taking_panics() {
+        let _lock = hook_lock();
+
+        run_in_custom_handler(|| {
+            for i in 0..1000 {
+                let mark = panic_mark();
+                let _ = std::panic::catch_unwind(|| panic!("drain {i}"));
+                assert!(take_panic_since(mark).is_some());
+            }
+        });
+
+        assert!(PANIC_BUFFER.with_borrow(|panics| panics.is_empty()));
+    }
 }
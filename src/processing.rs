@@ -1,22 +1,174 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::{Cursor, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use fxhash::{FxHashMap, FxHashSet};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::{AnimationDecoder, Delay, DynamicImage, Frame, GenericImage, GenericImageView, Rgba};
+use rayon::prelude::*;
+
+use crate::inputs::TestCandidate;
+use crate::options::{OutputLayout, PixelTolerance, Rect};
 
 use crate::outputs::{
-    TestChanged, TestError, TestFailed, TestOutput, TestOutputContext, TestOutputType, TestPassed, TestUnchanged,
+    FrameLocation, TestChanged, TestError, TestFailed, TestNoSnapshot, TestOutput, TestOutputContext, TestOutputType,
+    TestPassed, TestUnchanged,
 };
 
+/// The time each frame of an animated GIF is shown for.
+const GIF_FRAME_DELAY_MS: u32 = 100;
+
+#[derive(Debug, Clone)]
 pub struct TestReport {
     pub original_tests_count: usize,
+    /// Whether `changed` tests should count as failures for [TestReport::is_success].
+    pub treat_changes_as_failure: bool,
+    /// The results of all sub-tests, sorted by `rom_id`.
+    ///
+    /// Tests run in parallel, so without sorting the order would differ between runs. The sub-tests of a single test keep
+    /// the order in which its frames were produced. All other categories follow this order as well.
     pub test_outputs: Vec<TestOutput>,
     pub passed: Vec<TestPassed>,
     pub unchanged: Vec<TestUnchanged>,
     pub fails: Vec<TestFailed>,
     pub changed: Vec<TestChanged>,
     pub errors: Vec<TestError>,
+    /// Sub-tests without a snapshot, only used with [snapshot_only](crate::options::EmuRunnerOptions::snapshot_only).
+    pub no_snapshot: Vec<TestNoSnapshot>,
+    /// The results of all sub-tests grouped per test, in the order in which the tests first appear in `test_outputs`.
+    pub candidates: Vec<CandidateReport>,
+}
+
+/// The combined results of all sub-tests belonging to a single [TestCandidate].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CandidateReport {
+    pub candidate: Arc<TestCandidate>,
+    /// The amount of frames this test produced, `0` if it errored before producing any.
+    pub frame_count: usize,
+    pub passed: usize,
+    pub unchanged: usize,
+    pub changed: usize,
+    pub failed: usize,
+    pub errored: usize,
+    pub no_snapshot: usize,
+}
+
+impl CandidateReport {
+    fn new(candidate: Arc<TestCandidate>) -> Self {
+        Self {
+            candidate,
+            frame_count: 0,
+            passed: 0,
+            unchanged: 0,
+            changed: 0,
+            failed: 0,
+            errored: 0,
+            no_snapshot: 0,
+        }
+    }
+
+    /// The outcome of this test as a whole, which is the worst outcome of any of its sub-tests.
+    ///
+    /// A sequence test with a single failing frame thus counts as failed, no matter how many other frames passed.
+    pub fn outcome(&self) -> CandidateOutcome {
+        if self.errored > 0 {
+            CandidateOutcome::Errored
+        } else if self.failed > 0 {
+            CandidateOutcome::Failed
+        } else if self.changed > 0 {
+            CandidateOutcome::Changed
+        } else if self.no_snapshot > 0 {
+            CandidateOutcome::NoSnapshot
+        } else if self.unchanged > 0 {
+            CandidateOutcome::Unchanged
+        } else {
+            CandidateOutcome::Passed
+        }
+    }
+
+    fn add(&mut self, output: &TestOutput) {
+        if output.context.frame.is_some() {
+            self.frame_count += 1;
+        }
+
+        match &output.context.output {
+            TestOutputType::Unchanged(_) => self.unchanged += 1,
+            TestOutputType::Changed(_) => self.changed += 1,
+            TestOutputType::Failure(_) => self.failed += 1,
+            TestOutputType::Passed(_) => self.passed += 1,
+            TestOutputType::Error(_) => self.errored += 1,
+            TestOutputType::NoSnapshot(_) => self.no_snapshot += 1,
+        }
+    }
+}
+
+/// The outcome of a test as a whole, see [CandidateReport::outcome].
+///
+/// Ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateOutcome {
+    Passed,
+    Unchanged,
+    NoSnapshot,
+    Changed,
+    Failed,
+    Errored,
+}
+
+/// Which tests are compared to a snapshot, as opposed to only the frames of the previous run.
+///
+/// See [TestReport::snapshot_coverage].
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct SnapshotCoverage {
+    /// The amount of tests of which every sub-test was compared to a snapshot.
+    pub covered: usize,
+    /// Tests of which only some sub-tests have a snapshot, such as a sequence test which gained a frame.
+    pub partial: Vec<Arc<TestCandidate>>,
+    /// Tests without any snapshot, which can thus only ever be `changed` or `unchanged`.
+    pub uncovered: Vec<Arc<TestCandidate>>,
+}
+
+/// The amount of tests (not sub-tests) with each [CandidateOutcome].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct CandidateTally {
+    pub total: usize,
+    pub passed: usize,
+    pub unchanged: usize,
+    pub changed: usize,
+    pub failed: usize,
+    pub errored: usize,
+    pub no_snapshot: usize,
 }
 
 impl TestReport {
-    pub(crate) fn new(original_tests_count: usize, test_outputs: Vec<TestOutput>) -> Self {
+    pub(crate) fn new(
+        original_tests_count: usize,
+        mut test_outputs: Vec<TestOutput>,
+        treat_changes_as_failure: bool,
+    ) -> Self {
+        // A stable sort, to keep the sub-tests of a sequence test in order.
+        test_outputs.sort_by(|left, right| left.candidate.rom_id.cmp(&right.candidate.rom_id));
+
         let (mut passed, mut fails, mut unchanged, mut changed, mut errors) = (vec![], vec![], vec![], vec![], vec![]);
+        let mut no_snapshot = Vec::new();
+        let mut candidates: Vec<CandidateReport> = Vec::new();
+        let mut candidate_indices = FxHashMap::default();
+
+        for output in &test_outputs {
+            let index = *candidate_indices
+                .entry(output.candidate.rom_id.clone())
+                .or_insert_with(|| {
+                    candidates.push(CandidateReport::new(output.candidate.clone()));
+                    candidates.len() - 1
+                });
+
+            candidates[index].add(output);
+        }
 
         for report in test_outputs.clone() {
             let candidate = report.candidate;
@@ -27,6 +179,10 @@ impl TestReport {
                     candidate,
                     context: TestOutputContext {
                         time_taken: ctx.time_taken,
+                        frame: ctx.frame,
+                        metadata: ctx.metadata,
+                        cached: ctx.cached,
+                        peak_memory: ctx.peak_memory,
                         output: same,
                     },
                 }),
@@ -34,6 +190,10 @@ impl TestReport {
                     candidate,
                     context: TestOutputContext {
                         time_taken: ctx.time_taken,
+                        frame: ctx.frame,
+                        metadata: ctx.metadata,
+                        cached: ctx.cached,
+                        peak_memory: ctx.peak_memory,
                         output: changes,
                     },
                 }),
@@ -41,6 +201,10 @@ impl TestReport {
                     candidate,
                     context: TestOutputContext {
                         time_taken: ctx.time_taken,
+                        frame: ctx.frame,
+                        metadata: ctx.metadata,
+                        cached: ctx.cached,
+                        peak_memory: ctx.peak_memory,
                         output: fail,
                     },
                 }),
@@ -48,6 +212,10 @@ impl TestReport {
                     candidate,
                     context: TestOutputContext {
                         time_taken: ctx.time_taken,
+                        frame: ctx.frame,
+                        metadata: ctx.metadata,
+                        cached: ctx.cached,
+                        peak_memory: ctx.peak_memory,
                         output: pass,
                     },
                 }),
@@ -55,79 +223,811 @@ impl TestReport {
                     candidate,
                     context: error,
                 }),
+                TestOutputType::NoSnapshot(none) => no_snapshot.push(TestNoSnapshot {
+                    candidate,
+                    context: TestOutputContext {
+                        time_taken: ctx.time_taken,
+                        frame: ctx.frame,
+                        metadata: ctx.metadata,
+                        cached: ctx.cached,
+                        peak_memory: ctx.peak_memory,
+                        output: none,
+                    },
+                }),
             }
         }
 
         Self {
             original_tests_count,
+            treat_changes_as_failure,
             test_outputs,
             passed,
             unchanged,
             fails,
             changed,
             errors,
+            no_snapshot,
+            candidates,
+        }
+    }
+
+    /// Whether this run should be considered successful.
+    ///
+    /// A run is successful if no tests `failed` or `errored`. `passed` and `unchanged` tests never affect the outcome,
+    /// while `changed` tests only do so if [TestReport::treat_changes_as_failure] is set.
+    pub fn is_success(&self) -> bool {
+        self.fails.is_empty() && self.errors.is_empty() && (!self.treat_changes_as_failure || self.changed.is_empty())
+    }
+
+    /// The process exit code matching the outcome of this run, `0` on success and `1` otherwise.
+    ///
+    /// See [TestReport::is_success] for what constitutes a successful run.
+    pub fn exit_code(&self) -> i32 {
+        if self.is_success() {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// List every file written by this run, grouped by the sub-test it belongs to.
+    ///
+    /// Only files which exist on disk are included, so a [dry run](crate::options::EmuRunnerOptions::dry_run) results
+    /// in an empty manifest. Comparison copies of snapshots are not part of the report, and are thus not included.
+    pub fn manifest(&self) -> Vec<ManifestEntry> {
+        self.test_outputs
+            .iter()
+            .flat_map(|output| {
+                let mut paths = match &output.context.output {
+                    TestOutputType::Unchanged(_)
+                    | TestOutputType::Passed(_)
+                    | TestOutputType::Error(_)
+                    | TestOutputType::NoSnapshot(_) => vec![],
+                    TestOutputType::Failure(failure) => vec![(ArtifactKind::Failure, &failure.failure_path)],
+                    TestOutputType::Changed(changed) => vec![
+                        (ArtifactKind::Changed, &changed.changed_path),
+                        (ArtifactKind::Old, &changed.old_path),
+                    ],
+                };
+
+                if let Some(location) = &output.context.frame {
+                    paths.insert(0, (ArtifactKind::New, &location.new_path));
+                }
+
+                paths
+                    .into_iter()
+                    .filter(|(_, path)| path.exists())
+                    .map(move |(kind, path)| ManifestEntry {
+                        rom_id: output.candidate.rom_id.clone(),
+                        tag: output.context.frame.as_ref().and_then(|location| location.tag.clone()),
+                        outcome: output.context.output.name(),
+                        kind,
+                        path: path.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Write the [TestReport::manifest] to the given `path` as JSON.
+    pub fn write_manifest(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = std::fs::File::create(path).with_context(|| format!("Couldn't create manifest at {path:?}"))?;
+        let mut writer = std::io::BufWriter::new(file);
+
+        serde_json::to_writer_pretty(&mut writer, &self.manifest())?;
+        // Dropping the writer would silently discard a failure to write the last buffered part of the manifest.
+        writer
+            .flush()
+            .with_context(|| format!("Couldn't write manifest to {path:?}"))
+    }
+
+    /// Count the tests per [CandidateOutcome], where every test counts once regardless of how many frames it produced.
+    pub fn candidate_tally(&self) -> CandidateTally {
+        let mut tally = CandidateTally {
+            total: self.candidates.len(),
+            ..Default::default()
+        };
+
+        for candidate in &self.candidates {
+            match candidate.outcome() {
+                CandidateOutcome::Passed => tally.passed += 1,
+                CandidateOutcome::Unchanged => tally.unchanged += 1,
+                CandidateOutcome::Changed => tally.changed += 1,
+                CandidateOutcome::Failed => tally.failed += 1,
+                CandidateOutcome::Errored => tally.errored += 1,
+                CandidateOutcome::NoSnapshot => tally.no_snapshot += 1,
+            }
+        }
+
+        tally
+    }
+
+    /// Determine which tests have a snapshot, to track how much of the suite is covered by snapshots.
+    ///
+    /// A sub-test has a snapshot if it either `passed` or `failed`. Sub-tests which errored are ignored, so a test which
+    /// errored before producing any frames is neither covered nor uncovered.
+    pub fn snapshot_coverage(&self) -> SnapshotCoverage {
+        let mut coverage = SnapshotCoverage::default();
+
+        for candidate in &self.candidates {
+            let with_snapshot = candidate.passed + candidate.failed;
+            let without_snapshot = candidate.changed + candidate.unchanged + candidate.no_snapshot;
+
+            match (with_snapshot, without_snapshot) {
+                (0, 0) => {}
+                (_, 0) => coverage.covered += 1,
+                (0, _) => coverage.uncovered.push(candidate.candidate.clone()),
+                _ => coverage.partial.push(candidate.candidate.clone()),
+            }
+        }
+
+        coverage
+    }
+
+    /// The distribution of the time taken by the tests, `None` if no test was timed.
+    ///
+    /// Every test counts once, no matter how many sub-tests it has. Tests which errored before they could be timed are
+    /// excluded.
+    pub fn timing_percentiles(&self) -> Option<TimingPercentiles> {
+        let mut seen = FxHashSet::default();
+        let mut timings = self
+            .test_outputs
+            .iter()
+            .filter_map(|output| Some((&output.candidate.rom_id, output.context.time_taken?)))
+            .filter(|(rom_id, _)| seen.insert(*rom_id))
+            .map(|(_, time_taken)| time_taken)
+            .collect::<Vec<_>>();
+
+        timings.sort_unstable();
+        let max = *timings.last()?;
+
+        // The nearest-rank percentile, which is always one of the measured timings.
+        let percentile = |percentile: usize| {
+            let rank = (percentile * timings.len()).div_ceil(100).max(1);
+            timings[rank - 1]
+        };
+
+        Some(TimingPercentiles {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max,
+        })
+    }
+
+    /// Create a compact, serializable summary of this report.
+    ///
+    /// # Arguments
+    /// * `duration` - The time it took for the *entire* test suite to run.
+    pub fn summary(&self, duration: Duration) -> RunSummary {
+        let total = self.test_outputs.len();
+        let judged = total - self.no_snapshot.len();
+        let pass_rate =
+            if judged == 0 { 0.0 } else { (self.passed.len() + self.unchanged.len()) as f64 / judged as f64 };
+
+        RunSummary {
+            total,
+            passed: self.passed.len(),
+            unchanged: self.unchanged.len(),
+            changed: self.changed.len(),
+            failed: self.fails.len(),
+            errored: self.errors.len(),
+            no_snapshot: self.no_snapshot.len(),
+            cached: self.test_outputs.iter().filter(|output| output.context.cached).count(),
+            skipped: self.original_tests_count.saturating_sub(self.candidates.len()),
+            tests: self.candidate_tally(),
+            without_snapshot: self.snapshot_coverage().uncovered.len(),
+            timings: self.timing_percentiles(),
+            pass_rate,
+            duration,
+        }
+    }
+}
+
+/// A single file written by a test run, see [TestReport::manifest].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestEntry {
+    pub rom_id: String,
+    /// The tag of the frame this file belongs to, if any.
+    pub tag: Option<String>,
+    /// The outcome of the sub-test this file belongs to, matching the `outcome` of the JSON report.
+    pub outcome: &'static str,
+    pub kind: ArtifactKind,
+    pub path: PathBuf,
+}
+
+/// The role of a file written by a test run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    /// The frame as produced by the current run, in the `new` directory.
+    New,
+    /// A copy of a frame which didn't match its snapshot, in the `failures` directory.
+    Failure,
+    /// A copy of a frame which changed compared to the previous run, in the `changed` directory.
+    Changed,
+    /// The frame of the previous run a `changed` frame is compared to.
+    Old,
+}
+
+/// A machine-readable summary of a single test run.
+///
+/// All counts except `skipped`, `tests`, and `without_snapshot` are in terms of sub-tests, where a sequence test
+/// contributes one sub-test per frame.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RunSummary {
+    pub total: usize,
+    pub passed: usize,
+    pub unchanged: usize,
+    pub changed: usize,
+    pub failed: usize,
+    pub errored: usize,
+    /// The amount of sub-tests without a snapshot, see [TestOutputType::NoSnapshot].
+    pub no_snapshot: usize,
+    /// The amount of sub-tests whose frames were reused from a previous run, see [TestOutputContext::cached].
+    pub cached: usize,
+    /// The amount of tests (not sub-tests) which were provided, but never produced any output.
+    pub skipped: usize,
+    /// The counts in terms of tests instead of sub-tests, see [TestReport::candidate_tally].
+    pub tests: CandidateTally,
+    /// The amount of tests (not sub-tests) without any snapshot, see [TestReport::snapshot_coverage].
+    pub without_snapshot: usize,
+    /// The distribution of the time taken per test, see [TestReport::timing_percentiles].
+    pub timings: Option<TimingPercentiles>,
+    /// The fraction of sub-tests which were either `passed` or `unchanged`, in the range `[0, 1]`.
+    ///
+    /// Sub-tests without a snapshot are left out entirely, as they have no verdict.
+    pub pass_rate: f64,
+    pub duration: Duration,
+}
+
+/// Percentiles of the time taken per test, see [TestReport::timing_percentiles].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct TimingPercentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// The difference between two frames of equal dimensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameDiff {
+    /// The amount of pixels with at least one channel exceeding the allowed per-channel delta.
+    pub differing_pixels: usize,
+    pub total_pixels: usize,
+    /// The largest difference found in any single channel.
+    pub max_channel_delta: u8,
+}
+
+impl FrameDiff {
+    /// Whether this difference falls within the given `tolerance`.
+    ///
+    /// Without a `tolerance` the frames have to be exactly equal.
+    pub fn is_within(&self, tolerance: Option<&PixelTolerance>) -> bool {
+        match tolerance {
+            None => self.max_channel_delta == 0,
+            Some(_) if self.total_pixels == 0 => true,
+            Some(tolerance) => {
+                self.differing_pixels as f64 / self.total_pixels as f64 <= tolerance.max_differing_fraction as f64
+            }
         }
     }
 }
 
+/// Compute the difference between two frames of raw pixel data.
+///
+/// # Arguments
+/// * `channels` - The amount of bytes used for a single pixel in both frames.
+/// * `max_channel_delta` - The difference a channel may have before its pixel counts as differing.
+pub fn diff_frames(expected: &[u8], actual: &[u8], channels: usize, max_channel_delta: u8) -> FrameDiff {
+    let total_pixels = expected.len() / channels;
+
+    if expected == actual {
+        return FrameDiff {
+            total_pixels,
+            ..Default::default()
+        };
+    }
+
+    let mut diff = FrameDiff {
+        total_pixels,
+        ..Default::default()
+    };
+
+    for (expected, actual) in expected.chunks_exact(channels).zip(actual.chunks_exact(channels)) {
+        let pixel_delta = expected
+            .iter()
+            .zip(actual.iter())
+            .map(|(e, a)| e.abs_diff(*a))
+            .max()
+            .unwrap_or_default();
+
+        diff.max_channel_delta = diff.max_channel_delta.max(pixel_delta);
+        if pixel_delta > max_channel_delta {
+            diff.differing_pixels += 1;
+        }
+    }
+
+    diff
+}
+
+/// Compute the difference between two images, where pixels may deviate by the per-channel delta of the `tolerance`
+/// before they count as differing.
+///
+/// Images with differing colour types are compared in RGBA.
+///
+/// # Returns
+/// `None` if the images have differing dimensions.
+pub fn diff_images(
+    expected: &DynamicImage,
+    actual: &DynamicImage,
+    tolerance: Option<&PixelTolerance>,
+) -> Option<FrameDiff> {
+    if expected.dimensions() != actual.dimensions() {
+        return None;
+    }
+
+    let max_channel_delta = tolerance.map(|t| t.max_channel_delta).unwrap_or_default();
+
+    let diff = if expected.color() == actual.color() {
+        let channels = expected.color().channel_count() as usize;
+
+        diff_frames(expected.as_bytes(), actual.as_bytes(), channels, max_channel_delta)
+    } else {
+        diff_frames(
+            expected.to_rgba8().as_raw(),
+            actual.to_rgba8().as_raw(),
+            4,
+            max_channel_delta,
+        )
+    };
+
+    Some(diff)
+}
+
+/// Compute the combined difference of two sequences of images, see [diff_images].
+///
+/// # Returns
+/// `None` if the sequences have a different amount of images, or any pair of images has differing dimensions.
+pub fn diff_sequences(
+    expected: &[DynamicImage],
+    actual: &[DynamicImage],
+    tolerance: Option<&PixelTolerance>,
+) -> Option<FrameDiff> {
+    if expected.len() != actual.len() {
+        return None;
+    }
+
+    expected
+        .iter()
+        .zip(actual)
+        .try_fold(FrameDiff::default(), |total, (expected, actual)| {
+            let diff = diff_images(expected, actual, tolerance)?;
+
+            Some(FrameDiff {
+                differing_pixels: total.differing_pixels + diff.differing_pixels,
+                total_pixels: total.total_pixels + diff.total_pixels,
+                max_channel_delta: total.max_channel_delta.max(diff.max_channel_delta),
+            })
+        })
+}
+
+/// The part of every image of the sequence which should be compared.
+///
+/// The `masks` are blanked out first, after which the images are cropped to the given `region`. Borrows the sequence as
+/// is if there is nothing to mask or crop.
+pub fn comparison_view<'a>(
+    images: &'a [DynamicImage],
+    region: Option<&Rect>,
+    masks: &[Rect],
+) -> Cow<'a, [DynamicImage]> {
+    if region.is_none() && masks.is_empty() {
+        return Cow::Borrowed(images);
+    }
+
+    images
+        .iter()
+        .map(|image| {
+            let mut image = image.clone();
+
+            for mask in masks {
+                let x_end = mask.x.saturating_add(mask.width).min(image.width());
+                let y_end = mask.y.saturating_add(mask.height).min(image.height());
+
+                for y in mask.y..y_end {
+                    for x in mask.x..x_end {
+                        image.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+
+            match region {
+                Some(region) => image.crop_imm(region.x, region.y, region.width, region.height),
+                None => image,
+            }
+        })
+        .collect()
+}
+
+/// Check whether two sequences of images match, allowing for deviations within the given `tolerance`.
+pub fn sequences_match(expected: &[DynamicImage], actual: &[DynamicImage], tolerance: Option<&PixelTolerance>) -> bool {
+    diff_sequences(expected, actual, tolerance).is_some_and(|diff| diff.is_within(tolerance))
+}
+
+/// Compute a hash of the decoded contents of a sequence of images, including their dimensions and colour types.
+pub fn frames_hash(images: &[DynamicImage]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+
+    for image in images {
+        hasher.update(&image.width().to_le_bytes());
+        hasher.update(&image.height().to_le_bytes());
+        hasher.update(format!("{:?}", image.color()).as_bytes());
+        hasher.update(image.as_bytes());
+    }
+
+    hasher.finalize()
+}
+
+/// Encode the `images` as the frames of a looping, animated GIF.
+///
+/// GIFs are limited to 256 colours per frame, so the colours of the `images` may be altered in the process.
+pub fn encode_gif(images: &[DynamicImage]) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut encoder = GifEncoder::new_with_speed(&mut data, 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(images.iter().map(|image| {
+        Frame::from_parts(
+            image.to_rgba8(),
+            0,
+            0,
+            Delay::from_numer_denom_ms(GIF_FRAME_DELAY_MS, 1),
+        )
+    }))?;
+    drop(encoder);
+
+    Ok(data)
+}
+
+/// Decode all frames of a (potentially animated) GIF, in order.
+pub fn decode_gif(data: &[u8]) -> anyhow::Result<Vec<DynamicImage>> {
+    let frames = GifDecoder::new(Cursor::new(data))?.into_frames().collect_frames()?;
+
+    Ok(frames
+        .into_iter()
+        .map(|frame| DynamicImage::ImageRgba8(frame.into_buffer()))
+        .collect())
+}
+
+/// The path of the hash sidecar belonging to the frame at `frame_path`.
+pub fn hash_sidecar_path(frame_path: &Path) -> PathBuf {
+    let mut file_name = frame_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".blake3");
+
+    frame_path.with_file_name(file_name)
+}
+
+/// Whether the given `path` is a sidecar written by [write_hash_sidecar], rather than a frame.
+pub fn is_hash_sidecar(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "blake3")
+}
+
+pub fn write_hash_sidecar(frame_path: &Path, hash: &blake3::Hash) -> anyhow::Result<()> {
+    let sidecar_path = hash_sidecar_path(frame_path);
+
+    std::fs::write(&sidecar_path, hash.to_hex().as_bytes())
+        .with_context(|| format!("Couldn't write hash sidecar {sidecar_path:?}"))
+}
+
+/// Read the hash sidecar of the frame at `frame_path`.
+///
+/// # Returns
+/// `None` if there is no (valid) sidecar, or if the frame was modified after its sidecar was written.
+pub fn read_hash_sidecar(frame_path: &Path) -> Option<blake3::Hash> {
+    let sidecar_path = hash_sidecar_path(frame_path);
+    let frame_modified = std::fs::metadata(frame_path).and_then(|meta| meta.modified()).ok()?;
+    let sidecar_modified = std::fs::metadata(&sidecar_path).and_then(|meta| meta.modified()).ok()?;
+
+    if sidecar_modified < frame_modified {
+        return None;
+    }
+
+    let hex = std::fs::read_to_string(&sidecar_path).ok()?;
+    blake3::Hash::from_hex(hex.trim()).ok()
+}
+
 pub struct PathDefinitions<'a> {
     output_path: &'a Path,
+    layout: &'a OutputLayout,
     snapshot_path: &'a Path,
-    subfolder: Option<&'a Path>,
+    snapshot_variant: Option<&'a str>,
+    subfolder: Option<PathBuf>,
     file_name: String,
+    /// Whether the directories of the returned paths should be created if they don't exist yet.
+    create_directories: bool,
 }
 
 #[allow(dead_code)]
 impl<'a> PathDefinitions<'a> {
-    pub fn new(output_path: &'a Path, snapshot_path: &'a Path, subfolder: Option<&'a Path>, file_name: String) -> Self {
+    pub fn new(
+        output_path: &'a Path,
+        layout: &'a OutputLayout,
+        snapshot_path: &'a Path,
+        snapshot_variant: Option<&'a str>,
+        subfolder: Option<PathBuf>,
+        file_name: String,
+        create_directories: bool,
+    ) -> Self {
         PathDefinitions {
             output_path,
+            layout,
             snapshot_path,
+            snapshot_variant,
             subfolder,
             file_name,
+            create_directories,
+        }
+    }
+
+    /// The locations where the frame of this definition is saved, and where its snapshot would be found.
+    pub fn frame_location(&self, tag: Option<String>) -> FrameLocation {
+        FrameLocation {
+            tag,
+            new_path: self.path_in(&self.layout.new_path(self.output_path), &self.file_name),
+            snapshot_path: self.snapshot_path(),
         }
     }
 
     pub fn new_path(&self) -> anyhow::Result<PathBuf> {
-        self.check_and_create(&crate::new_path(self.output_path), &self.file_name)
+        self.check_and_create(&self.layout.new_path(self.output_path), &self.file_name)
     }
 
-    pub fn old_path(&self) -> anyhow::Result<PathBuf> {
-        self.check_and_create(&crate::old_path(self.output_path), &self.file_name)
+    /// Like the snapshot path the `old` path is only read from, so its directory is never created.
+    pub fn old_path(&self) -> PathBuf {
+        self.path_in(&self.layout.old_path(self.output_path), &self.file_name)
     }
 
     pub fn changed_path(&self) -> anyhow::Result<PathBuf> {
-        self.check_and_create(&crate::changed_path(self.output_path), &self.file_name)
+        self.check_and_create(&self.layout.changed_path(self.output_path), &self.file_name)
     }
 
     pub fn changed_path_with_suffix(&self, suffix: &str) -> anyhow::Result<PathBuf> {
-        let suffix_name = self.file_name.replace(".png", &format!("_{}.png", suffix));
+        let suffix_name = self.file_name_with_suffix(suffix);
 
-        self.check_and_create(&crate::changed_path(self.output_path), &suffix_name)
+        self.check_and_create(&self.layout.changed_path(self.output_path), &suffix_name)
     }
 
     pub fn failed_path(&self) -> anyhow::Result<PathBuf> {
-        self.check_and_create(&crate::failures_path(self.output_path), &self.file_name)
+        self.check_and_create(&self.layout.failures_path(self.output_path), &self.file_name)
     }
 
     pub fn failed_path_with_suffix(&self, suffix: &str) -> anyhow::Result<PathBuf> {
-        let suffix_name = self.file_name.replace(".png", &format!("_{}.png", suffix));
+        let suffix_name = self.file_name_with_suffix(suffix);
 
-        self.check_and_create(&crate::failures_path(self.output_path), &suffix_name)
+        self.check_and_create(&self.layout.failures_path(self.output_path), &suffix_name)
     }
 
-    pub fn snapshot_path(&self) -> anyhow::Result<PathBuf> {
-        self.check_and_create(self.snapshot_path, &self.file_name)
+    /// The snapshot this frame is primarily compared to, which is either a single file or a directory of acceptable
+    /// snapshots.
+    ///
+    /// With a [snapshot_variant](crate::options::EmuRunnerOptions::snapshot_variant) the snapshot of the variant is
+    /// preferred over the shared snapshot if it exists. If there is no snapshot yet this is where the shared snapshot
+    /// would be. The snapshot path is only read from, so unlike the other paths its directory is never created.
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.snapshot_entries()
+            .next()
+            .unwrap_or_else(|| self.path_in(self.snapshot_path, &self.file_name))
+    }
+
+    /// All snapshot files this frame is compared to, in order of preference.
+    ///
+    /// A snapshot which is a directory contributes every file in it, sorted by name, as any of them is acceptable.
+    pub fn existing_snapshot_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+
+        for entry in self.snapshot_entries() {
+            if !entry.is_dir() {
+                paths.push(entry);
+                continue;
+            }
+
+            let mut alternatives = std::fs::read_dir(&entry)
+                .with_context(|| format!("Couldn't read snapshot directory {entry:?}"))?
+                .map(|file| Ok(file?.path()))
+                .collect::<std::io::Result<Vec<_>>>()
+                .with_context(|| format!("Couldn't read snapshot directory {entry:?}"))?;
+            alternatives.retain(|path| path.is_file() && !is_hash_sidecar(path));
+            alternatives.sort();
+
+            paths.extend(alternatives);
+        }
+
+        Ok(paths)
+    }
+
+    /// The snapshots of this frame which exist, either files or directories, see [PathDefinitions::snapshot_path].
+    fn snapshot_entries(&self) -> impl Iterator<Item = PathBuf> {
+        let variant_path = self
+            .snapshot_variant
+            .map(|variant| self.path_in(&self.snapshot_path.join(variant), &self.file_name));
+
+        variant_path
+            .into_iter()
+            .chain([self.path_in(self.snapshot_path, &self.file_name)])
+            .filter(|path| path.exists())
+    }
+
+    /// The path of this frame relative to the given directory, as it would be laid out in any of the output
+    /// directories.
+    pub fn relative_path_in(&self, path: &Path) -> PathBuf {
+        self.path_in(path, &self.file_name)
+    }
+
+    fn file_name_with_suffix(&self, suffix: &str) -> String {
+        let file_name = Path::new(&self.file_name);
+        let stem = file_name.file_stem().unwrap_or_default().to_string_lossy();
+
+        match file_name.extension() {
+            Some(extension) => format!("{stem}_{suffix}.{}", extension.to_string_lossy()),
+            None => format!("{stem}_{suffix}"),
+        }
     }
 
     fn check_and_create(&self, path: &Path, filename: &str) -> anyhow::Result<PathBuf> {
-        if let Some(folder) = self.subfolder.as_ref() {
+        if let (Some(folder), true) = (self.subfolder.as_ref(), self.create_directories) {
             let folder_path = path.join(folder);
-            std::fs::create_dir_all(&folder_path)?;
+            std::fs::create_dir_all(&folder_path)
+                .with_context(|| format!("Couldn't create output directory {folder_path:?}"))?;
+        }
+
+        Ok(self.path_in(path, filename))
+    }
+
+    fn path_in(&self, path: &Path, filename: &str) -> PathBuf {
+        match self.subfolder.as_ref() {
+            Some(folder) => path.join(folder).join(filename),
+            None => path.join(filename),
+        }
+    }
+}
+
+/// The differences between the `new` directories of two output directories, see [compare_output_dirs].
+///
+/// All paths are relative to the `new` directories, and thus named after the `rom_id` of the test they belong to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DirDiff {
+    /// Files present in both directories whose contents differ.
+    pub changed: Vec<PathBuf>,
+    /// Files only present in the first directory, such as those of a test which was removed.
+    pub only_in_a: Vec<PathBuf>,
+    /// Files only present in the second directory, such as those of a newly added test.
+    pub only_in_b: Vec<PathBuf>,
+}
 
-            Ok(folder_path.join(filename))
+impl DirDiff {
+    /// Whether both directories contain the same files, with the same contents.
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.only_in_a.is_empty() && self.only_in_b.is_empty()
+    }
+}
+
+/// Compare the `new` directories of two output directories from separate runs, without running any emulators.
+///
+/// Useful for finding which tests changed between two builds of an emulator, for example while bisecting. Images are
+/// compared by their pixels, so frames which were merely encoded differently (such as with another
+/// [CompressionTradeoff](crate::options::CompressionTradeoff)) are not reported. Any other file, such as text output,
+/// is compared byte for byte. Hash sidecars are ignored.
+///
+/// Both directories are expected to use the default [OutputLayout].
+///
+/// # Returns
+/// The [DirDiff], with every list sorted by path.
+pub fn compare_output_dirs(a: &Path, b: &Path) -> anyhow::Result<DirDiff> {
+    let layout = OutputLayout::default();
+    let (a, b) = (layout.new_path(a), layout.new_path(b));
+    let (files_a, files_b) = (list_relative_files(&a)?, list_relative_files(&b)?);
+
+    let mut changed = files_a
+        .par_iter()
+        .filter(|file| files_b.contains(*file))
+        .map(|file| files_match(&a.join(file), &b.join(file)).map(|matches| (!matches).then(|| file.clone())))
+        .filter_map(Result::transpose)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    changed.sort();
+
+    Ok(DirDiff {
+        changed,
+        only_in_a: files_a.difference(&files_b).cloned().collect(),
+        only_in_b: files_b.difference(&files_a).cloned().collect(),
+    })
+}
+
+/// All files in the `root` directory and its sub-directories, relative to `root`, except hash sidecars.
+fn list_relative_files(root: &Path) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut result = BTreeSet::new();
+    let mut directories = vec![root.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory).with_context(|| format!("Couldn't read directory {directory:?}"))? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                directories.push(path);
+            } else if !is_hash_sidecar(&path) {
+                result.insert(path.strip_prefix(root)?.to_path_buf());
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Whether the files at `a` and `b` have the same contents, comparing the pixels if both are images.
+fn files_match(a: &Path, b: &Path) -> anyhow::Result<bool> {
+    let data_a = std::fs::read(a).with_context(|| format!("Couldn't read {a:?}"))?;
+    let data_b = std::fs::read(b).with_context(|| format!("Couldn't read {b:?}"))?;
+
+    if data_a == data_b {
+        return Ok(true);
+    }
+
+    let decode = |data: &[u8]| -> Option<Vec<DynamicImage>> {
+        if a.extension().is_some_and(|extension| extension == "gif") {
+            decode_gif(data).ok()
         } else {
-            Ok(path.join(filename))
+            image::load_from_memory(data).ok().map(|image| vec![image])
+        }
+    };
+
+    Ok(match (decode(&data_a), decode(&data_b)) {
+        (Some(images_a), Some(images_b)) => {
+            images_a.len() == images_b.len()
+                && images_a.iter().zip(&images_b).all(|(image_a, image_b)| {
+                    image_a.dimensions() == image_b.dimensions() && image_a.to_rgba8() == image_b.to_rgba8()
+                })
         }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use std::time::Duration;
+
+    #[test]
+    fn hash_sidecars_round_trip_until_the_frame_changes() {
+        let dir = std::env::temp_dir().join(format!("emu_test_runner_sidecar_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let frame_path = dir.join("frame.png");
+        let hash = frames_hash(&[DynamicImage::ImageRgba8(RgbaImage::new(2, 2))]);
+
+        std::fs::write(&frame_path, b"frame").unwrap();
+        write_hash_sidecar(&frame_path, &hash).unwrap();
+        let fresh = read_hash_sidecar(&frame_path);
+
+        // Pretend the frame was overwritten after its sidecar was written.
+        let sidecar_modified = std::fs::metadata(hash_sidecar_path(&frame_path))
+            .unwrap()
+            .modified()
+            .unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&frame_path)
+            .unwrap()
+            .set_modified(sidecar_modified + Duration::from_secs(10))
+            .unwrap();
+        let stale = read_hash_sidecar(&frame_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(fresh, Some(hash));
+        assert_eq!(stale, None);
+        assert!(is_hash_sidecar(&hash_sidecar_path(&frame_path)));
     }
 }
@@ -1,11 +1,17 @@
+use std::borrow::Cow;
+use std::fmt::Write;
 use std::path::{Path, PathBuf};
 
+use anyhow::Context;
+
+use crate::options::OutputLayout;
+
 pub const NEW_DIR_NAME: &str = "new";
 pub const OLD_DIR_NAME: &str = "old";
 pub const CHANGED_DIR_NAME: &str = "changed";
 pub const FAILED_DIR_NAME: &str = "failures";
 
-/// Will clean and setup the directory structure in the output directory as follows:
+/// Will clean and setup the directory structure in the output directory as follows (names depend on the `layout`):
 ///
 ///
 /// * OUTPUT_DIR
@@ -13,55 +19,266 @@ pub const FAILED_DIR_NAME: &str = "failures";
 ///     * /old
 ///     * /changed
 ///     * /failures
-pub fn setup_output_directory(output: &Path) -> anyhow::Result<()> {
-    let new_dir = new_path(output);
-    let old_dir = old_path(output);
-    let changed_dir = changed_path(output);
-    let failures = failures_path(output);
-
-    let _ = std::fs::remove_dir_all(&old_dir);
-    // Move the `new` dir to the `old`
-    if new_dir.exists() {
-        std::fs::rename(&new_dir, &old_dir)?;
+///
+/// If `rotate` is set the previous `new` directory replaces the `old` directory, otherwise the `old` directory is left
+/// untouched and the previous `new` directory is discarded.
+///
+/// Only a failure to create the `new` directory is an error. If the `old` directory can't be rotated, or the `changed`
+/// and `failures` directories can't be created, the run can still continue in a degraded state. These problems are
+/// instead returned as warnings.
+pub fn setup_output_directory(output: &Path, layout: &OutputLayout, rotate: bool) -> anyhow::Result<Vec<String>> {
+    let new_dir = layout.new_path(output);
+    let old_dir = layout.old_path(output);
+    let changed_dir = layout.changed_path(output);
+    let failures = layout.failures_path(output);
+    let mut warnings = Vec::new();
+
+    if rotate {
+        if let Err(e) = rotate_new_to_old(&new_dir, &old_dir) {
+            if old_dir.exists() {
+                warnings.push(format!(
+                    "Couldn't rotate {new_dir:?} to {old_dir:?}, changes are compared to whatever remains in {old_dir:?}: {e}"
+                ));
+                let _ = std::fs::remove_dir_all(&new_dir);
+            } else {
+                // The `new` directory holds the only copy of the previous run, which is better than nothing at all.
+                warnings.push(format!(
+                    "Couldn't rotate {new_dir:?} to {old_dir:?}, the previous output is left in {new_dir:?} and changes can't be detected: {e}"
+                ));
+            }
+        }
+    } else {
+        let _ = std::fs::remove_dir_all(&new_dir);
     }
 
     let _ = std::fs::remove_dir_all(&changed_dir);
     let _ = std::fs::remove_dir_all(&failures);
 
-    std::fs::create_dir_all(new_dir)?;
-    std::fs::create_dir_all(changed_dir)?;
-    std::fs::create_dir_all(failures)?;
+    std::fs::create_dir_all(&new_dir).with_context(|| format!("Couldn't create output directory {new_dir:?}"))?;
+
+    for dir in [changed_dir, failures] {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warnings.push(format!("Couldn't create output directory {dir:?}: {e}"));
+        }
+    }
+
+    Ok(warnings)
+}
+
+/// Link every file of the `old` directory which isn't in the `new` directory into the latter.
+///
+/// After a run of only some of the tests, this keeps the output of the other tests around as the baseline of the next
+/// run. Files are hard linked where possible, and copied otherwise.
+pub fn carry_forward_old_output(output: &Path, layout: &OutputLayout) -> anyhow::Result<()> {
+    let old_dir = layout.old_path(output);
+    let new_dir = layout.new_path(output);
+    let mut directories = vec![old_dir.clone()];
+
+    while let Some(directory) = directories.pop() {
+        let entries = match std::fs::read_dir(&directory) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            entries => entries.with_context(|| format!("Couldn't read directory {directory:?}"))?,
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            let target = new_dir.join(path.strip_prefix(&old_dir)?);
+
+            if path.is_dir() {
+                directories.push(path);
+            } else if !target.exists() {
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent).with_context(|| format!("Couldn't create directory {parent:?}"))?;
+                }
+
+                if std::fs::hard_link(&path, &target).is_err() {
+                    std::fs::copy(&path, &target).with_context(|| format!("Couldn't copy {path:?} to {target:?}"))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Empty the `changed` and `failures` directories in the output directory, leaving the `new` and `old` directories as is.
+pub fn clear_transient_directories(output: &Path, layout: &OutputLayout) -> anyhow::Result<()> {
+    for dir in [layout.changed_path(output), layout.failures_path(output)] {
+        remove_dir_if_exists(&dir).with_context(|| format!("Couldn't remove output directory {dir:?}"))?;
+        std::fs::create_dir_all(&dir).with_context(|| format!("Couldn't create output directory {dir:?}"))?;
+    }
+
+    Ok(())
+}
+
+/// Replace the `old_dir` with the `new_dir`.
+///
+/// The `old_dir` is first moved aside, and only removed once the `new_dir` took its place. If that fails the `old_dir`
+/// is moved back, so a failed rotation never loses both directories.
+fn rotate_new_to_old(new_dir: &Path, old_dir: &Path) -> std::io::Result<()> {
+    if !new_dir.exists() {
+        return remove_dir_if_exists(old_dir);
+    }
+
+    let mut previous_dir = old_dir.as_os_str().to_owned();
+    previous_dir.push(".rotating");
+    let previous_dir = PathBuf::from(previous_dir);
+
+    // A leftover of an earlier rotation which was interrupted.
+    remove_dir_if_exists(&previous_dir)?;
+
+    if old_dir.exists() {
+        std::fs::rename(old_dir, &previous_dir)?;
+    }
+
+    if let Err(e) = std::fs::rename(new_dir, old_dir) {
+        if previous_dir.exists() {
+            let _ = std::fs::rename(&previous_dir, old_dir);
+        }
+
+        return Err(e);
+    }
+
+    let _ = std::fs::remove_dir_all(&previous_dir);
 
     Ok(())
 }
 
+fn remove_dir_if_exists(dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
 /// Setup the directory where one can save the Snapshots for tests.
 ///
 /// A test with an associated snapshot will fail if it starts to differ from the established baseline.
+///
+/// The directory is only created if it doesn't exist yet. Snapshots are only ever read while running tests, so a
+/// directory which can't be created (for example, on a read-only mount) is not an error, all tests will simply lack a
+/// snapshot.
 pub fn setup_snapshot_directory(snapshot: &Path) -> anyhow::Result<()> {
-    Ok(std::fs::create_dir_all(snapshot)?)
+    if !snapshot.exists() {
+        let _ = std::fs::create_dir_all(snapshot);
+    }
+
+    Ok(())
 }
 
+/// The `old` directory in the default [OutputLayout].
 pub fn old_path(output: &Path) -> PathBuf {
-    output.join(OLD_DIR_NAME)
+    OutputLayout::default().old_path(output)
 }
 
+/// The `new` directory in the default [OutputLayout].
 pub fn new_path(output: &Path) -> PathBuf {
-    output.join(NEW_DIR_NAME)
+    OutputLayout::default().new_path(output)
 }
 
+/// The `changed` directory in the default [OutputLayout].
 pub fn changed_path(output: &Path) -> PathBuf {
-    output.join(CHANGED_DIR_NAME)
+    OutputLayout::default().changed_path(output)
 }
 
+/// The `failures` directory in the default [OutputLayout].
 pub fn failures_path(output: &Path) -> PathBuf {
-    output.join(FAILED_DIR_NAME)
+    OutputLayout::default().failures_path(output)
 }
 
-pub fn rom_id_to_png(rom_id: &str, suffix: Option<&str>) -> String {
+/// The name of a file belonging to the given `rom_id`, see [sanitize_file_name] for how unsafe `rom_id`s and `suffix`es
+/// are handled.
+pub fn rom_id_to_file_name(rom_id: &str, suffix: Option<&str>, extension: &str) -> String {
+    let rom_id = sanitize_file_name(rom_id);
+
     if let Some(suffix) = suffix {
-        format!("{rom_id}_{suffix}.png")
+        format!("{rom_id}_{}.{extension}", sanitize_file_name(suffix))
     } else {
-        format!("{rom_id}.png")
+        format!("{rom_id}.{extension}")
+    }
+}
+
+/// Make the given `name` safe to use as (part of) a file name on all platforms.
+///
+/// Characters which aren't allowed in file names, such as path separators, are percent-encoded, as is `%` itself. This
+/// keeps distinct names distinct, while names which were already safe are returned as is.
+///
+/// ```
+/// # use emu_test_runner::sanitize_file_name;
+/// assert_eq!(sanitize_file_name("cpu_instrs"), "cpu_instrs");
+/// assert_eq!(sanitize_file_name("blargg/cpu:01"), "blargg%2Fcpu%3A01");
+/// ```
+pub fn sanitize_file_name(name: &str) -> Cow<'_, str> {
+    let is_unsafe = |c: char| matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '%') || c.is_control();
+    // A name consisting of only dots would refer to the current or parent directory instead.
+    let only_dots = name.chars().all(|c| c == '.');
+
+    if !only_dots && !name.contains(is_unsafe) {
+        return Cow::Borrowed(name);
+    }
+
+    let mut result = String::with_capacity(name.len());
+
+    for c in name.chars() {
+        if is_unsafe(c) || (only_dots && c == '.') {
+            let mut buffer = [0; 4];
+            for byte in c.encode_utf8(&mut buffer).bytes() {
+                let _ = write!(result, "%{byte:02X}");
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    Cow::Owned(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("emu_test_runner_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rotation_replaces_old_with_new() {
+        let output = temp_output_dir("rotation");
+        let layout = OutputLayout::default();
+        std::fs::create_dir_all(layout.old_path(&output)).unwrap();
+        std::fs::write(layout.old_path(&output).join("a.png"), b"old").unwrap();
+        std::fs::create_dir_all(layout.new_path(&output)).unwrap();
+        std::fs::write(layout.new_path(&output).join("a.png"), b"new").unwrap();
+
+        let warnings = setup_output_directory(&output, &layout, true).unwrap();
+        let rotated = std::fs::read(layout.old_path(&output).join("a.png")).unwrap();
+        let new_is_empty = std::fs::read_dir(layout.new_path(&output)).unwrap().next().is_none();
+        std::fs::remove_dir_all(&output).unwrap();
+
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(rotated, b"new");
+        assert!(new_is_empty);
+    }
+
+    #[test]
+    fn failed_rotation_keeps_the_previous_output() {
+        let output = temp_output_dir("failed_rotation");
+        // The parent of the `old` directory doesn't exist, so `new` can't be renamed to it.
+        let layout = OutputLayout {
+            old_dir: "missing/old".to_string(),
+            ..Default::default()
+        };
+        std::fs::create_dir_all(layout.new_path(&output)).unwrap();
+        std::fs::write(layout.new_path(&output).join("a.png"), b"previous").unwrap();
+
+        let warnings = setup_output_directory(&output, &layout, true).unwrap();
+        let kept = std::fs::read(layout.new_path(&output).join("a.png")).ok();
+        std::fs::remove_dir_all(&output).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(kept.as_deref(), Some(&b"previous"[..]));
     }
 }
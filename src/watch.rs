@@ -0,0 +1,226 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use fxhash::FxHashSet;
+use notify::{RecursiveMode, Watcher};
+
+use crate::inputs::TestCandidate;
+use crate::outputs::EmulatorOutput;
+use crate::{setup, EmuRunnerError, EmuTestRunner};
+
+/// How long the file system has to be quiet before the changed tests are re-ran.
+///
+/// Saving or rebuilding a ROM tends to result in a burst of events, which should only result in a single re-run.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+impl EmuTestRunner {
+    /// Run all tests once, and then keep re-running the tests whose ROM or snapshots change on disk.
+    ///
+    /// The directories containing the ROMs of the tests are watched, as is the
+    /// [snapshot_path](crate::options::EmuRunnerOptions::snapshot_path). Every burst of changes results in a single
+    /// [EmuTestRunner::run_tests_with_report] of just the affected tests, so the `formatter` is notified of each re-run
+    /// as if it were a separate run. Like with [EmuTestRunner::run_tests_filtered] the output of the other tests is kept,
+    /// so they keep their baseline.
+    ///
+    /// # Arguments
+    /// * `tests` - Discovers the tests to run, and is called again after every change. This allows newly added ROMs to
+    ///   be picked up, for example by calling [TestCandidate::find_all_in_directory].
+    /// * `emu_run` - Runs a single test, see [EmuTestRunner::run_tests].
+    ///
+    /// # Returns
+    /// Only returns once the file system watcher stops, or if the run as a whole failed. Failed tests don't stop the
+    /// watch, and neither does a failure to re-discover the `tests`, which is instead reported as a warning.
+    pub fn watch<T, F, R>(&self, tests: T, emu_run: F) -> Result<(), EmuRunnerError>
+    where
+        T: Fn() -> anyhow::Result<Vec<TestCandidate>>,
+        F: Fn(&TestCandidate, Vec<u8>) -> R + Send + Sync + std::panic::RefUnwindSafe + 'static,
+        R: Into<EmulatorOutput>,
+    {
+        let emu_run = Arc::new(emu_run);
+        let shared_emu_run = || {
+            let emu_run = emu_run.clone();
+            move |test: &TestCandidate, rom_data: Vec<u8>| emu_run(test, rom_data)
+        };
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .context("Couldn't create a file system watcher")
+            .map_err(EmuRunnerError::Watch)?;
+
+        setup::setup_snapshot_directory(&self.options.snapshot_path).map_err(EmuRunnerError::DirectorySetup)?;
+        let snapshot_path = canonical_path(&self.options.snapshot_path);
+        watcher
+            .watch(&snapshot_path, RecursiveMode::Recursive)
+            .with_context(|| format!("Couldn't watch snapshot directory {snapshot_path:?}"))
+            .map_err(EmuRunnerError::Watch)?;
+
+        let mut rom_directories = FxHashSet::default();
+        let candidates = tests().map_err(EmuRunnerError::Watch)?;
+        self.watch_rom_directories(&mut watcher, &mut rom_directories, &candidates)?;
+        self.run_tests_with_report(candidates.into_iter(), shared_emu_run())?;
+
+        // The watcher only stops sending events once it's dropped, which happens if its thread failed.
+        while let Ok(event) = receiver.recv() {
+            let mut changed_paths = FxHashSet::default();
+            let mut event = Ok(event);
+
+            loop {
+                match event {
+                    Ok(Ok(event)) if !event.kind.is_access() => changed_paths.extend(event.paths),
+                    Ok(Ok(_)) => {}
+                    Ok(Err(error)) => self.watch_warning(&format!("File system watcher error: {error}"))?,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+
+                event = receiver.recv_timeout(DEBOUNCE);
+            }
+
+            let candidates = match tests() {
+                Ok(candidates) => candidates,
+                Err(error) => {
+                    self.watch_warning(&format!("Couldn't discover the tests to re-run: {error:#}"))?;
+                    continue;
+                }
+            };
+            self.watch_rom_directories(&mut watcher, &mut rom_directories, &candidates)?;
+
+            let affected = candidates
+                .into_iter()
+                .filter(|candidate| {
+                    is_affected(
+                        candidate,
+                        &changed_paths,
+                        &snapshot_path,
+                        self.options.snapshot_variant.as_deref(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            if !affected.is_empty() {
+                self.run_tests_inner(affected.into_iter(), shared_emu_run(), |_| {}, true)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start watching the directories containing the ROMs of the `candidates` which aren't watched yet.
+    fn watch_rom_directories(
+        &self,
+        watcher: &mut impl Watcher,
+        watched: &mut FxHashSet<PathBuf>,
+        candidates: &[TestCandidate],
+    ) -> Result<(), EmuRunnerError> {
+        let directories = candidates
+            .iter()
+            .filter(|candidate| candidate.rom_data.is_none())
+            .filter_map(|candidate| candidate.rom_path.parent())
+            .map(|parent| canonical_path(if parent.as_os_str().is_empty() { Path::new(".") } else { parent }))
+            .collect::<FxHashSet<_>>();
+
+        for directory in directories {
+            if watched.contains(&directory) {
+                continue;
+            }
+
+            match watcher.watch(&directory, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    watched.insert(directory);
+                }
+                Err(error) => self.watch_warning(&format!("Couldn't watch ROM directory {directory:?}: {error}"))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn watch_warning(&self, warning: &str) -> Result<(), EmuRunnerError> {
+        self.formatter
+            .handle_warning(warning)
+            .map_err(EmuRunnerError::Formatter)
+    }
+}
+
+/// Whether the ROM of the `candidate`, or one of its snapshots, is among the `changed_paths`.
+///
+/// Snapshots are recognised by their name starting with the (sanitized) `rom_id`, or by being in a sub-folder named after
+/// it. This can match the snapshots of another test whose `rom_id` shares a prefix, which merely results in that test
+/// being re-ran as well. Snapshots in the directory of the `snapshot_variant` are recognised the same way.
+fn is_affected(
+    candidate: &TestCandidate,
+    changed_paths: &FxHashSet<PathBuf>,
+    snapshot_path: &Path,
+    snapshot_variant: Option<&str>,
+) -> bool {
+    if candidate.rom_data.is_none() && changed_paths.contains(&canonical_path(&candidate.rom_path)) {
+        return true;
+    }
+
+    let file_name = setup::sanitize_file_name(&candidate.rom_id);
+    let sub_test_prefix = format!("{file_name}_");
+
+    changed_paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(snapshot_path).ok())
+        .map(|relative| match snapshot_variant {
+            Some(variant) => relative.strip_prefix(variant).unwrap_or(relative),
+            None => relative,
+        })
+        .filter_map(|relative| relative.components().next())
+        .map(|component| Path::new(component.as_os_str()))
+        .any(|name| {
+            let is_named =
+                |name: Option<&std::ffi::OsStr>| name.is_some_and(|name| name.to_string_lossy() == file_name);
+            is_named(Some(name.as_os_str()))
+                || is_named(name.file_stem())
+                || name.to_string_lossy().starts_with(&sub_test_prefix)
+        })
+}
+
+/// The canonical form of the `path`, as the watcher reports the paths of events relative to the watched directory.
+///
+/// Falls back to the `path` itself if it doesn't exist (anymore).
+fn canonical_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_variant_changes_affect_their_test() {
+        let candidate = TestCandidate::new("cpu", "/roms/cpu.gb");
+        let snapshot_path = Path::new("/snapshots");
+        let changed = |path: &str| FxHashSet::from_iter([PathBuf::from(path)]);
+
+        assert!(is_affected(
+            &candidate,
+            &changed("/snapshots/cpu.png"),
+            snapshot_path,
+            None
+        ));
+        assert!(is_affected(
+            &candidate,
+            &changed("/snapshots/cgb/cpu.png"),
+            snapshot_path,
+            Some("cgb")
+        ));
+        assert!(is_affected(
+            &candidate,
+            &changed("/snapshots/cgb/cpu/0.png"),
+            snapshot_path,
+            Some("cgb")
+        ));
+        assert!(!is_affected(
+            &candidate,
+            &changed("/snapshots/cgb/ppu.png"),
+            snapshot_path,
+            Some("cgb")
+        ));
+    }
+}